@@ -0,0 +1,159 @@
+use crate::Spin;
+
+use std::ops::Range;
+
+/// Quantum numbers of a single particle state of the harmonic oscillator. The quantum numbers of
+/// the directions a system does not have are always 0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuantumNumbers {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    pub spin: Spin,
+}
+
+/// Number of spatial orbitals with energy quantum number `shell`, i.e. nx + ny + nz = shell.
+pub fn shell_degeneracy(shell: usize, dim: usize) -> usize {
+    match dim {
+        1 => 1,
+        2 => shell + 1,
+        _ => (shell + 1) * (shell + 2) / 2,
+    }
+}
+
+/// Returns the number of filled shells of a system of `n` particles, or an error if the particles
+/// do not fill a whole number of shells.
+pub fn filled_shells(n: usize, dim: usize) -> Result<usize, String> {
+    let mut filled = 0;
+    let mut shells = 0;
+    while filled < n {
+        filled += 2 * shell_degeneracy(shells, dim);
+        shells += 1;
+    }
+
+    if filled == n && n > 0 {
+        Ok(shells)
+    } else {
+        Err(format!("{} particles do not make a closed-shell system in {} dimensions.", n, dim))
+    }
+}
+
+/// Returns the quantum numbers of state `i`, with the states ordered by increasing energy. Inside
+/// a shell, nx is decreasing and then ny is decreasing, and every orbital holds a spin up and a
+/// spin down particle. In two dimensions, the first states are (0, 0), (0, 0), (1, 0), (1, 0),
+/// (0, 1), (0, 1), (2, 0) and so on.
+pub fn quantum_numbers(i: usize, dim: usize) -> QuantumNumbers {
+    let spin = if i.is_multiple_of(2) { Spin::Up } else { Spin::Down };
+    let mut orbital = i / 2;
+
+    // Find the shell of the orbital, and its index within the shell
+    let mut shell = 0;
+    while orbital >= shell_degeneracy(shell, dim) {
+        orbital -= shell_degeneracy(shell, dim);
+        shell += 1;
+    }
+
+    let (nx, ny, nz) = match dim {
+        1 => (shell, 0, 0),
+        2 => (shell - orbital, orbital, 0),
+        _ => {
+            // There are shell - nx + 1 orbitals for every nx, starting with nx = shell
+            let mut nx = shell;
+            while orbital > shell - nx {
+                orbital -= shell - nx + 1;
+                nx -= 1;
+            }
+            let ny = shell - nx - orbital;
+            (nx, ny, shell - nx - ny)
+        }
+    };
+
+    QuantumNumbers { nx, ny, nz, spin }
+}
+
+/// Returns the states occupied by a closed-shell system of `n` particles.
+pub fn occupation(n: usize, dim: usize) -> Result<Vec<QuantumNumbers>, String> {
+    filled_shells(n, dim)?;
+    Ok((0..n).map(|i| quantum_numbers(i, dim)).collect())
+}
+
+/// Returns the two groups of particles sharing a Slater determinant in a system of `n` particles.
+/// The first half of the particles are spin up and the second half spin down, the same convention
+/// as the Jastrow factor uses.
+pub fn spin_blocks(n: usize) -> [Range<usize>; 2] {
+    [0..n / 2, n / 2..n]
+}
+
+/// Returns the group of particles sharing a Slater determinant with particle `p`.
+pub fn spin_block(p: usize, n: usize) -> Range<usize> {
+    if p < n / 2 { 0..n / 2 } else { n / 2..n }
+}
+
+/// Returns the state of column `k` of the Slater matrix of `n` particles. The columns are ordered
+/// in the same way as the particles, so the first n/2 columns are the spin up orbitals and the last
+/// n/2 columns the same orbitals with spin down.
+pub fn slater_state(k: usize, n: usize, dim: usize) -> QuantumNumbers {
+    let spin_down = k >= n / 2;
+    quantum_numbers(2 * (k % (n / 2)) + spin_down as usize, dim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filled_shells() {
+        let magic_2d: Vec<usize> = (1..=7).map(|s| (0..s).map(|i| 2 * shell_degeneracy(i, 2)).sum()).collect();
+        assert_eq!(magic_2d, vec![2, 6, 12, 20, 30, 42, 56]);
+        let magic_3d: Vec<usize> = (1..=5).map(|s| (0..s).map(|i| 2 * shell_degeneracy(i, 3)).sum()).collect();
+        assert_eq!(magic_3d, vec![2, 8, 20, 40, 70]);
+
+        assert_eq!(filled_shells(56, 2), Ok(7));
+        assert_eq!(filled_shells(8, 1), Ok(4));
+        assert_eq!(filled_shells(40, 3), Ok(4));
+        assert!(filled_shells(4, 2).is_err());
+        assert!(filled_shells(12, 3).is_err());
+        assert!(filled_shells(0, 2).is_err());
+    }
+
+    #[test]
+    fn test_two_dimensions() {
+        // The table of states we used to hard-code
+        let want = [(0, 0), (1, 0), (0, 1), (2, 0), (1, 1), (0, 2), (3, 0), (2, 1), (1, 2), (0, 3)];
+        let got = occupation(20, 2).unwrap();
+        for (i, state) in got.iter().enumerate() {
+            assert_eq!((state.nx, state.ny, state.nz), (want[i / 2].0, want[i / 2].1, 0));
+            assert_eq!(state.spin, if i % 2 == 0 { Spin::Up } else { Spin::Down });
+        }
+    }
+
+    #[test]
+    fn test_three_dimensions() {
+        let states = occupation(40, 3).unwrap();
+        // Every orbital in a shell is distinct and has the right energy
+        let mut seen = vec![];
+        for state in states.iter().step_by(2) {
+            let orbital = (state.nx, state.ny, state.nz);
+            assert!(!seen.contains(&orbital));
+            seen.push(orbital);
+        }
+        assert_eq!(&seen[..4], &[(0, 0, 0), (1, 0, 0), (0, 1, 0), (0, 0, 1)]);
+        assert_eq!(seen[4..10].iter().filter(|(x, y, z)| x + y + z == 2).count(), 6);
+        assert_eq!(seen[10..20].iter().filter(|(x, y, z)| x + y + z == 3).count(), 10);
+    }
+
+    #[test]
+    fn test_slater_states() {
+        let n = 12;
+        let states: Vec<QuantumNumbers> = (0..n).map(|k| slater_state(k, n, 2)).collect();
+        for block in spin_blocks(n).iter() {
+            for (k, state) in block.clone().zip(states[block.clone()].iter()) {
+                assert_eq!(spin_block(k, n), *block);
+                let orbital = quantum_numbers(2 * (k - block.start), 2);
+                assert_eq!((state.nx, state.ny), (orbital.nx, orbital.ny));
+            }
+        }
+        assert!(states[..6].iter().all(|state| state.spin == Spin::Up));
+        assert!(states[6..].iter().all(|state| state.spin == Spin::Down));
+    }
+}
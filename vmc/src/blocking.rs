@@ -0,0 +1,198 @@
+// Automated blocking as described by Marius Jonsson, "Standard error estimation by an automated
+// blocking method", Phys. Rev. E 98, 043304 (2018). This is a port of the Python implementation in
+// `result_analysis/lib/blocking.py`, but done on the fly, so that we never have to keep the whole
+// time series in memory.
+
+/// Quantiles of the chi-squared distribution at 99% for 1, 2, ..., 30 degrees of freedom.
+const Q: [f64; 30] = [
+    6.634897, 9.210340, 11.344867, 13.276704, 15.086272, 16.811894, 18.475307, 20.090235,
+    21.665994, 23.209251, 24.724970, 26.216967, 27.688250, 29.141238, 30.577914, 31.999927,
+    33.408664, 34.805306, 36.190869, 37.566235, 38.932173, 40.289360, 41.638398, 42.979820,
+    44.314105, 45.641683, 46.962942, 48.278236, 49.587884, 50.892181,
+];
+
+/// Result of a blocking analysis of a time series.
+#[derive(Clone, Copy, Debug)]
+pub struct BlockingResult {
+    /// Mean of the time series
+    pub mean: f64,
+    /// Sample variance of the time series, ignoring correlations
+    pub variance: f64,
+    /// Standard error of the mean, corrected for autocorrelation
+    pub error: f64,
+    /// Number of samples needed to get one independent sample. This is 1 for uncorrelated data
+    pub autocorrelation_time: f64,
+}
+
+/// Sums needed to get the variance and lag-one autocovariance at one level of blocking.
+#[derive(Clone, Debug, Default)]
+struct Level {
+    n: usize,
+    sum: f64,
+    sum_sqrd: f64,
+    sum_lag: f64,
+    first: f64,
+    last: f64,
+    // First value of a pair waiting to be averaged into the next level
+    pending: Option<f64>,
+}
+
+/// Accumulates a time series one value at a time, keeping only O(log n) numbers.
+#[derive(Clone, Debug, Default)]
+pub struct Blocker {
+    levels: Vec<Level>,
+}
+
+impl Blocker {
+    pub fn new() -> Self {
+        Blocker { levels: Vec::new() }
+    }
+
+    /// Adds the next value of the time series
+    pub fn push(&mut self, x: f64) {
+        let mut value = x;
+        let mut i = 0;
+        loop {
+            if i == self.levels.len() {
+                self.levels.push(Level::default());
+            }
+            let level = &mut self.levels[i];
+
+            if level.n == 0 {
+                level.first = value;
+            } else {
+                level.sum_lag += level.last * value;
+            }
+            level.n += 1;
+            level.sum += value;
+            level.sum_sqrd += value * value;
+            level.last = value;
+
+            // Every pair of values is averaged into one value on the next level
+            match level.pending.take() {
+                Some(prev) => {
+                    value = 0.5 * (prev + value);
+                    i += 1;
+                }
+                None => {
+                    level.pending = Some(value);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Returns the number of values pushed so far
+    pub fn len(&self) -> usize {
+        self.levels.first().map_or(0, |level| level.n)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Does the blocking analysis of the values pushed so far. Returns `None` if there are fewer
+    /// than two values. If the series is too short for the test to decide on a blocking level, the
+    /// deepest level is used, which might underestimate the error.
+    pub fn analyze(&self) -> Option<BlockingResult> {
+        if self.len() < 2 {
+            return None;
+        }
+
+        let total = &self.levels[0];
+        let mu = total.sum / total.n as f64;
+
+        // Variance and autocovariance on every level with at least two values
+        let levels: Vec<(f64, f64, f64)> = self.levels.iter()
+            .take_while(|level| level.n >= 2)
+            .map(|level| {
+                let n = level.n as f64;
+                let mean = level.sum / n;
+                let s = (level.sum_sqrd / n - mean * mean).max(0.);
+                let gamma = (level.sum_lag
+                    - mu * (level.sum - level.last)
+                    - mu * (level.sum - level.first)
+                    + (n - 1.) * mu * mu) / n;
+                (n, s, gamma)
+            })
+            .collect();
+        let d = levels.len();
+
+        // Test observable from the theorem, which is chi-squared distributed
+        let mut m = vec![0.; d];
+        let mut acc = 0.;
+        for k in (0..d).rev() {
+            let (n, s, gamma) = levels[k];
+            if s > 0. {
+                acc += n * (gamma / s).powi(2);
+            }
+            m[k] = acc;
+        }
+
+        let k = (0..d).find(|&k| m[k] < Q[k.min(Q.len() - 1)]).unwrap_or(d - 1);
+
+        let (n0, s0, _) = levels[0];
+        let (nk, sk, _) = levels[k];
+        let error_sqrd = sk / nk;
+        let autocorrelation_time = if s0 > 0. { error_sqrd / (s0 / n0) } else { 1. };
+
+        Some(BlockingResult {
+            mean: mu,
+            variance: s0,
+            error: error_sqrd.sqrt(),
+            autocorrelation_time,
+        })
+    }
+}
+
+/// Does a blocking analysis of a whole time series.
+pub fn block(data: &[f64]) -> Option<BlockingResult> {
+    let mut blocker = Blocker::new();
+    for &x in data {
+        blocker.push(x);
+    }
+    blocker.analyze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use rand_distr::{Distribution, Normal};
+
+    fn ar1(n: usize, phi: f64, seed: u64) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let normal = Normal::new(0., 1.).unwrap();
+        let mut x = 0.;
+        (0..n).map(|_| { x = phi * x + normal.sample(&mut rng); x }).collect()
+    }
+
+    #[test]
+    fn test_uncorrelated() {
+        let data = ar1(1 << 16, 0., 1);
+        let result = block(&data).unwrap();
+        let naive = (result.variance / data.len() as f64).sqrt();
+        assert!(result.mean.abs() < 5. * naive);
+        assert!((result.error / naive - 1.).abs() < 0.2);
+        assert!(result.autocorrelation_time < 1.5);
+    }
+
+    #[test]
+    fn test_correlated() {
+        // The autocorrelation time of an AR(1) process is (1 + phi) / (1 - phi)
+        let phi: f64 = 0.9;
+        let data = ar1(1 << 18, phi, 2);
+        let result = block(&data).unwrap();
+        let want = (1. + phi) / (1. - phi);
+        assert!((result.autocorrelation_time / want - 1.).abs() < 0.3);
+    }
+
+    #[test]
+    fn test_constant_and_short_series() {
+        assert!(block(&[1.]).is_none());
+        let result = block(&[2.; 100]).unwrap();
+        assert_eq!(result.mean, 2.);
+        assert_eq!(result.error, 0.);
+        assert_eq!(result.autocorrelation_time, 1.);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{optimizer::{Method, Objective, Stopping}, parameters::{Overrides, Range}};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "vmc", about = "Variational Monte Carlo solver for quantum dots.")]
+pub enum Command {
+    /// Runs a single configuration ten times and writes the energies to file
+    Simple {
+        #[structopt(flatten)]
+        overrides: Overrides,
+        /// Record the sampled values of every n'th cycle to a separate file for each run
+        #[structopt(long)]
+        record_every: Option<usize>,
+    },
+    /// Runs simulations at several (omega, alpha, beta) points in parallel
+    Multiple {
+        #[structopt(flatten)]
+        overrides: Overrides,
+        /// Trap frequencies of each point
+        #[structopt(long, use_delimiter = true)]
+        omegas: Vec<f64>,
+        /// Values of alpha at each point
+        #[structopt(long, use_delimiter = true)]
+        alphas: Vec<f64>,
+        /// Values of beta at each point
+        #[structopt(long, use_delimiter = true)]
+        betas: Vec<f64>,
+    },
+    /// Runs simulations on a grid of omega, alpha and beta spread over a pool of threads, and
+    /// writes them all to one file
+    Scan {
+        #[structopt(flatten)]
+        overrides: Overrides,
+        /// Trap frequencies as start:end:points, --omega by default
+        #[structopt(long)]
+        omegas: Option<Range>,
+        /// Values of alpha as start:end:points, --alpha by default
+        #[structopt(long)]
+        alphas: Option<Range>,
+        /// Values of beta as start:end:points, --beta by default
+        #[structopt(long)]
+        betas: Option<Range>,
+        /// Number of threads, one per core by default
+        #[structopt(long)]
+        threads: Option<u8>,
+    },
+    /// Optimizes alpha and beta from a grid of starting points
+    Sgd {
+        #[structopt(flatten)]
+        overrides: Overrides,
+        #[structopt(flatten)]
+        sgd: SgdOptions,
+        /// Starting values of alpha
+        #[structopt(long, use_delimiter = true)]
+        start_alphas: Vec<f64>,
+        /// Starting values of beta
+        #[structopt(long, use_delimiter = true)]
+        start_betas: Vec<f64>,
+    },
+    /// Optimizes alpha and beta for several trap frequencies
+    SgdOmega {
+        #[structopt(flatten)]
+        overrides: Overrides,
+        #[structopt(flatten)]
+        sgd: SgdOptions,
+        /// Trap frequencies to optimize for
+        #[structopt(long, use_delimiter = true)]
+        omegas: Vec<f64>,
+    },
+    /// Estimates the energy on a grid of alpha and beta from a single run at --alpha and --beta,
+    /// by correlated sampling
+    Reweight {
+        #[structopt(flatten)]
+        overrides: Overrides,
+        /// Values of alpha to estimate the energy at, eleven values around --alpha by default
+        #[structopt(long, use_delimiter = true)]
+        alphas: Vec<f64>,
+        /// Values of beta to estimate the energy at, eleven values around --beta by default
+        #[structopt(long, use_delimiter = true)]
+        betas: Vec<f64>,
+    },
+    /// Evaluates the two-particle wavefunction as a function of the inter-particle distance
+    Onebody {
+        #[structopt(flatten)]
+        overrides: Overrides,
+    },
+    /// Times a single long simulation
+    Performance {
+        #[structopt(flatten)]
+        overrides: Overrides,
+    },
+    /// Compares the analytic derivatives of the wavefunction with finite differences
+    Check {
+        #[structopt(flatten)]
+        overrides: Overrides,
+        /// Number of random configurations to check
+        #[structopt(long, default_value = "100")]
+        configurations: usize,
+        /// Step size of the finite differences
+        #[structopt(long, default_value = "0.0001")]
+        step: f64,
+        /// Fail if any discrepancy is larger than this
+        #[structopt(long, default_value = "0.0001")]
+        tolerance: f64,
+    },
+}
+
+// Options controlling the optimization (not a doc comment, see `Overrides`).
+#[derive(StructOpt, Clone, Debug)]
+pub struct SgdOptions {
+    /// What to minimize [energy|variance|<weight of the variance in a mix with the energy>]. Any
+    /// objective but the energy is minimized with correlated sampling, and ignores --optimizer.
+    #[structopt(long, default_value = "energy")]
+    pub objective: Objective,
+    /// Largest number of steps taken on the same configurations with correlated sampling
+    #[structopt(long, default_value = "10")]
+    pub max_updates: usize,
+    /// Optimizer of the variational parameters
+    /// [gradient-descent|stochastic-reconfiguration|momentum|adam|line-search|linear-method]
+    #[structopt(long, default_value = "gradient-descent")]
+    pub optimizer: Method,
+    /// Learning rate of the optimizer, or time step of the stochastic reconfiguration. Adam moves
+    /// every parameter by about this much per iteration.
+    #[structopt(long, default_value = "0.05")]
+    pub learning_rate: f64,
+    /// Relative shift of the diagonal of the overlap matrix in the stochastic reconfiguration
+    #[structopt(long, default_value = "0.001")]
+    pub regularization: f64,
+    /// Shift of the diagonal of the Hamiltonian in the linear method, larger values give shorter
+    /// steps
+    #[structopt(long, default_value = "0.001")]
+    pub stabilization: f64,
+    /// Largest change of any parameter in one step of the linear method. Longer steps are
+    /// shortened by raising the stabilization.
+    #[structopt(long, default_value = "0.2")]
+    pub max_step: f64,
+    /// Fraction of the velocity kept every iteration by the momentum optimizer
+    #[structopt(long, default_value = "0.9")]
+    pub momentum: f64,
+    /// Stop when every energy derivative is within this many standard errors of zero
+    #[structopt(long, default_value = "1")]
+    pub significance: f64,
+    /// Also stop when every energy derivative is smaller than this
+    #[structopt(long, default_value = "0")]
+    pub tolerance: f64,
+    /// Number of iterations in a row the energy derivatives have to meet --significance or
+    /// --tolerance for before stopping
+    #[structopt(long, default_value = "5")]
+    pub patience: usize,
+    /// Maximum number of iterations
+    #[structopt(long, default_value = "150")]
+    pub max_iterations: usize,
+}
+
+impl SgdOptions {
+    /// Returns when the optimization stops
+    pub fn stopping(&self) -> Stopping {
+        Stopping { tolerance: self.tolerance, significance: self.significance, patience: self.patience, max_iterations: self.max_iterations }
+    }
+}
@@ -0,0 +1,116 @@
+use crate::parameters::{Overrides, Parameters};
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Reads a configuration file into a set of overrides. The format is decided by the extension of
+/// the file, which must be either `.toml` or `.json`. Any parameter not present in the file keeps
+/// the default of the run.
+pub fn load(path: &Path) -> Result<Overrides, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|why| format!("Unable to read {:?}: {}", path, why))?;
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&contents).map_err(|why| format!("Unable to parse {:?}: {}", path, why)),
+        Some("json") => serde_json::from_str(&contents).map_err(|why| format!("Unable to parse {:?}: {}", path, why)),
+        _ => Err(format!("Unknown config format of {:?}, expected a .toml or .json file.", path)),
+    }
+}
+
+/// Writes the resolved parameters of a run as TOML next to the data file at `data_path`, so that
+/// `data/N2/foo.csv` gets the companion `data/N2/foo.toml`.
+pub fn write_next_to(params: &Parameters, data_path: &Path) -> Result<PathBuf, String> {
+    let path = data_path.with_extension("toml");
+    let contents = toml::to_string(params).map_err(|why| format!("Unable to serialize parameters: {}", why))?;
+    fs::write(&path, contents).map_err(|why| format!("Unable to write {:?}: {}", path, why))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameters::{PairInteraction, Potential, Sampler};
+
+    #[test]
+    fn test_parse_toml() {
+        let overrides: Overrides = toml::from_str(
+            "n = 6\nomega = 0.5\njastrow = true\nsampler = \"importance\"\noutput = \"results\"\n"
+        ).unwrap();
+        assert_eq!(overrides.n, Some(6));
+        assert_eq!(overrides.omega, Some(0.5));
+        assert_eq!(overrides.jastrow, Some(true));
+        assert!(matches!(overrides.sampler, Some(Sampler::Importance)));
+        assert_eq!(overrides.output, Some(PathBuf::from("results")));
+        assert_eq!(overrides.alpha, None);
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let overrides: Overrides = serde_json::from_str(
+            r#"{ "alpha": 0.98, "beta": 0.43, "mc_cycles": 1000, "sampler": "brute-force" }"#
+        ).unwrap();
+        assert_eq!(overrides.alpha, Some(0.98));
+        assert_eq!(overrides.beta, Some(0.43));
+        assert_eq!(overrides.mc_cycles, Some(1000));
+        assert!(matches!(overrides.sampler, Some(Sampler::BruteForce)));
+    }
+
+    #[test]
+    fn test_rejects_unknown_keys() {
+        assert!(toml::from_str::<Overrides>("alhpa = 1.0").is_err());
+    }
+
+    #[test]
+    fn test_flags_take_precedence() {
+        let mut path = std::env::temp_dir();
+        path.push("vmc_test_flags_take_precedence.toml");
+        fs::write(&path, "alpha = 0.5\nbeta = 0.25\n").unwrap();
+
+        let overrides = Overrides { config: Some(path.clone()), alpha: Some(0.75), ..Default::default() };
+        let params = overrides.apply(Parameters::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(params.alpha, 0.75);
+        assert_eq!(params.beta, 0.25);
+    }
+
+    #[test]
+    fn test_resolved_parameters_roundtrip() {
+        let potential = Potential::FiniteWell { depth: 5., radius: 3. };
+        let interaction = PairInteraction::Yukawa { screening: 0.5 };
+        let overrides = Overrides { n: Some(6), potential: Some(potential), interaction: Some(interaction), ..Default::default() };
+        let params = overrides.apply(Parameters::default()).unwrap();
+        let overrides: Overrides = toml::from_str(&toml::to_string(&params).unwrap()).unwrap();
+        let roundtrip = overrides.apply(Parameters::default()).unwrap();
+        assert_eq!(roundtrip.n, 6);
+        assert_eq!(roundtrip.potential, potential);
+        assert_eq!(roundtrip.interaction, interaction);
+        assert_eq!(roundtrip.output, params.output);
+    }
+
+    #[test]
+    fn test_derived_seeds_roundtrip() {
+        let mut data_path = std::env::temp_dir();
+        data_path.push("vmc_test_derived_seeds_roundtrip.csv");
+        // Half of the raw SplitMix64 outputs have the highest bit set, so some of these would
+        // overflow the signed integers of TOML
+        for stream in 0..8 {
+            let seed = crate::utils::stream_seed(u64::MAX, stream);
+            let path = write_next_to(&Parameters { seed, ..Default::default() }, &data_path).unwrap();
+            let params = load(&path).unwrap().apply(Parameters::default()).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(params.seed, seed);
+        }
+
+        // Explicit seeds are only accepted as long as they can be written
+        let overrides = Overrides { seed: Some(i64::MAX as u64), ..Default::default() };
+        let path = write_next_to(&overrides.apply(Parameters::default()).unwrap(), &data_path).unwrap();
+        let params = load(&path).unwrap().apply(Parameters::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(params.seed, i64::MAX as u64);
+        let overrides = Overrides { seed: Some(u64::MAX), ..Default::default() };
+        assert!(overrides.apply(Parameters::default()).is_err());
+    }
+}
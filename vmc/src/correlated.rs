@@ -0,0 +1,227 @@
+// Correlated sampling. Configurations sampled with one set of variational parameters are reused to
+// estimate averages with other parameters, by weighting every configuration with the ratio
+// |psi_new / psi_old|^2 of the wavefunctions. Estimates at nearby parameters then share the noise
+// of the configurations, so their differences are far more accurate than those of independent runs,
+// as long as the weights stay even enough, which the effective sample size measures.
+
+use crate::{block, Metropolis, Particle, System, TrialWaveFunction};
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim};
+
+/// Configurations sampled by Metropolis, together with the logarithm of the wavefunction they were
+/// sampled from.
+#[derive(Clone, Debug)]
+pub struct Walkers {
+    pub configurations: Vec<Vec<Particle>>,
+    log_psi: Vec<f64>,
+}
+
+/// Averages over `Walkers` reweighted to the parameters of a wavefunction, and the gradient of the
+/// cost (1 - w) E + w variance with regards to the parameters, for a variance weight w. The
+/// gradient is empty if it was not asked for.
+#[derive(Clone, Debug)]
+pub struct Reweighted {
+    pub energy: f64,
+    /// Standard error of the energy from the blocking method
+    pub error: f64,
+    pub variance: f64,
+    pub cost: f64,
+    pub gradient: Vec<f64>,
+    /// Standard error of each derivative of the cost from the blocking method
+    pub gradient_error: Option<Vec<f64>>,
+    /// Number of equally weighted configurations that would give estimates as accurate as the
+    /// weighted ones, (sum w)^2 / sum w^2. It equals the number of configurations at the parameters
+    /// they were sampled with, and drops as the weights grow uneven further away.
+    pub effective_sample_size: f64,
+}
+
+impl Walkers {
+    /// Samples `n` configurations from `sys`, keeping one every `interval` Metropolis steps after
+    /// equilibrating for a quarter of the steps, like `monte_carlo` does.
+    pub fn sample<T: Metropolis, D: Dim, W: TrialWaveFunction>(
+        sys: &mut System<D, W>,
+        metro: &mut T,
+        n: usize,
+        interval: usize,
+    ) -> Result<Self, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        for _ in 0..n * interval / 4 {
+            metro.step(sys)?;
+        }
+
+        let mut configurations = Vec::with_capacity(n);
+        let mut log_psi = Vec::with_capacity(n);
+        for i in 0..n * interval {
+            metro.step(sys)?;
+            if (i + 1) % interval == 0 {
+                log_psi.push(sys.wf.log_psi(&sys.particles)?);
+                configurations.push(sys.particles.clone());
+            }
+        }
+        Ok(Walkers { configurations, log_psi })
+    }
+
+    pub fn len(&self) -> usize {
+        self.configurations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.configurations.is_empty()
+    }
+
+    /// Reweights the walkers to the wavefunction of `sys`, with a cost whose variance weight is
+    /// `variance_weight`. The configuration and state of `sys` are overwritten.
+    pub fn reweight<D: Dim, W: TrialWaveFunction>(&self, sys: &mut System<D, W>, variance_weight: f64) -> Result<Reweighted, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        self.evaluate(sys, Some(variance_weight))
+    }
+
+    /// Reweights the walkers to the wavefunction of `sys` like `reweight`, but leaves out the
+    /// gradient, which is much cheaper. The cost is the energy.
+    pub fn reweight_energy<D: Dim, W: TrialWaveFunction>(&self, sys: &mut System<D, W>) -> Result<Reweighted, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        self.evaluate(sys, None)
+    }
+
+    fn evaluate<D: Dim, W: TrialWaveFunction>(&self, sys: &mut System<D, W>, variance_weight: Option<f64>) -> Result<Reweighted, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        if self.is_empty() {
+            return Err("There are no walkers to reweight.".to_owned());
+        }
+
+        let n = self.len();
+        let mut log_weights = Vec::with_capacity(n);
+        let mut energies = Vec::with_capacity(n);
+        let mut derivatives = Vec::with_capacity(n);
+        let mut energy_derivatives = Vec::with_capacity(n);
+        for (configuration, log_psi) in self.configurations.iter().zip(&self.log_psi) {
+            sys.set_particles(configuration.clone())?;
+            log_weights.push(2. * (sys.wf.log_psi(&sys.particles)? - log_psi));
+            energies.push(sys.hamiltonian.energy(sys)?.0);
+            if variance_weight.is_some() {
+                derivatives.push(sys.wf.log_derivatives(&sys.particles, &sys.state)?);
+                energy_derivatives.push(sys.local_energy_derivatives()?);
+            }
+        }
+
+        // Normalized so the weights sum to one, shifted first so that none of them overflow
+        let largest = log_weights.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let mut weights: Vec<f64> = log_weights.iter().map(|log_weight| (log_weight - largest).exp()).collect();
+        let total: f64 = weights.iter().sum();
+        weights.iter_mut().for_each(|w| *w /= total);
+        let mean = |f: &dyn Fn(usize) -> f64| (0..n).map(|i| weights[i] * f(i)).sum::<f64>();
+
+        let energy = mean(&|i| energies[i]);
+        let variance = mean(&|i| (energies[i] - energy).powi(2));
+        // The error of a ratio of weighted sums is that of the weighted deviations from it
+        let deviations: Vec<f64> = (0..n).map(|i| n as f64 * weights[i] * (energies[i] - energy)).collect();
+        let error = block(&deviations).map_or(f64::NAN, |blocked| blocked.error);
+        let effective_sample_size = 1. / weights.iter().map(|w| w.powi(2)).sum::<f64>();
+
+        let variance_weight = match variance_weight {
+            Some(variance_weight) => variance_weight,
+            None => {
+                return Ok(Reweighted {
+                    energy,
+                    error,
+                    variance,
+                    cost: energy,
+                    gradient: vec![],
+                    gradient_error: None,
+                    effective_sample_size,
+                })
+            }
+        };
+        let parameters = derivatives[0].len();
+        let mean_derivatives: Vec<f64> = (0..parameters).map(|k| mean(&|i| derivatives[i][k])).collect();
+
+        // The derivative of the weights is 2 (O_k - <O_k>) w, which gives each configuration a
+        // contribution to the gradient. Their weighted means are the gradient, and the blocking
+        // method gives its error from the series of weighted contributions.
+        let mut gradient = Vec::with_capacity(parameters);
+        let mut gradient_error = Some(Vec::with_capacity(parameters));
+        for k in 0..parameters {
+            let contributions: Vec<f64> = (0..n)
+                .map(|i| {
+                    let o = derivatives[i][k] - mean_derivatives[k];
+                    let e = energies[i] - energy;
+                    let d_energy = 2. * o * e + energy_derivatives[i][k];
+                    let d_variance = 2. * o * (e.powi(2) - variance) + 2. * e * energy_derivatives[i][k];
+                    n as f64 * weights[i] * ((1. - variance_weight) * d_energy + variance_weight * d_variance)
+                })
+                .collect();
+            gradient.push(contributions.iter().sum::<f64>() / n as f64);
+            gradient_error = gradient_error.and_then(|mut errors: Vec<f64>| {
+                errors.push(block(&contributions)?.error);
+                Some(errors)
+            });
+        }
+
+        Ok(Reweighted {
+            energy,
+            error,
+            variance,
+            cost: (1. - variance_weight) * energy + variance_weight * variance,
+            gradient,
+            gradient_error,
+            effective_sample_size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BruteForceMetropolis, GaussianProduct};
+    use nalgebra::Dynamic;
+
+    #[test]
+    fn test_reweighting() {
+        let wf = GaussianProduct { alpha: 0.9, omega: 1., anisotropy: [1.; 3] };
+        let mut sys = System::<Dynamic, _>::new(4, 2, wf, false, false, 1., 9).unwrap();
+        let walkers = Walkers::sample(&mut sys, &mut BruteForceMetropolis::new(1.), 2000, 4).unwrap();
+        assert_eq!(walkers.len(), 2000);
+
+        // At the parameters they were sampled with every walker weighs the same
+        let reference = walkers.reweight(&mut sys, 0.).unwrap();
+        assert!((reference.effective_sample_size - 2000.).abs() < 1e-6);
+        assert!((reference.energy - 4.).abs() < 0.1);
+
+        // The exact ground state has the same local energy everywhere, whatever the weights
+        sys.wf.alpha = 1.;
+        let exact = walkers.reweight(&mut sys, 1.).unwrap();
+        assert!((exact.energy - 4.).abs() < 1e-9);
+        assert!(exact.variance < 1e-12 && exact.cost < 1e-12);
+        assert!(exact.gradient[0].abs() < 1e-6);
+        assert!(exact.effective_sample_size < 2000.);
+
+        // The energy alone agrees with the full estimate, and at the reference its error is the
+        // blocking error of the sampled energies
+        let energy = walkers.reweight_energy(&mut sys).unwrap();
+        assert_eq!((energy.energy, energy.variance), (exact.energy, exact.variance));
+        assert!(energy.gradient.is_empty());
+        sys.wf.alpha = 0.9;
+        let energies: Vec<f64> = walkers.configurations.iter()
+            .map(|configuration| {
+                sys.set_particles(configuration.clone()).unwrap();
+                sys.hamiltonian.energy(&sys).unwrap().0
+            })
+            .collect();
+        let blocked = block(&energies).unwrap();
+        let energy = walkers.reweight_energy(&mut sys).unwrap();
+        assert!((energy.energy - blocked.mean).abs() < 1e-12);
+        assert!((energy.error - blocked.error).abs() < 1e-12);
+
+        // Further away the weights grow uneven
+        sys.wf.alpha = 1.2;
+        let far = walkers.reweight_energy(&mut sys).unwrap();
+        assert!(far.effective_sample_size < exact.effective_sample_size);
+    }
+}
@@ -1,56 +1,114 @@
-use crate::System;
+use crate::{interaction::{interaction_energy, total_interaction, Interaction, InteractionEnergy}, potential::ExternalPotential, Particle, System, TrialWaveFunction};
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim};
 
+use std::sync::Arc;
+
+/// The Hamiltonian of the system: the kinetic energy, an external potential and, if the system is
+/// interacting, the interaction between every pair of particles.
 #[derive(Clone)]
-pub struct Hamiltonian;
+pub struct Hamiltonian {
+    external: Arc<dyn ExternalPotential>,
+    interaction: Arc<dyn Interaction>,
+}
 
 impl Hamiltonian {
+    /// Creates a Hamiltonian with the given external potential and pair interaction
+    pub fn new<P, I>(external: P, interaction: I) -> Self
+    where
+        P: ExternalPotential + 'static,
+        I: Interaction + 'static,
+    {
+        Hamiltonian { external: Arc::new(external), interaction: Arc::new(interaction) }
+    }
+
+    /// Returns the interaction energy of `particles`, with the contribution of every pair
+    pub fn interaction(&self, particles: &[Particle]) -> InteractionEnergy {
+        interaction_energy(self.interaction.as_ref(), particles)
+    }
+
     // --- Kinetic energy ---
-    fn kinetic<const N: usize>(sys: &System<N>) -> Result<f64, String> {
+    fn kinetic<D: Dim, W: TrialWaveFunction>(sys: &System<D, W>) -> Result<f64, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
         Ok(-0.5 * sys.laplace()?)
     }
 
     // --- Potential energy ---
-    fn potential<const N: usize>(sys: &System<N>) -> f64 {
-        // Harmonic oscillator
-        let external_potential: f64 = sys.particles.iter().map(|x| x.squared_sum()).sum();
-
-        // Repulsive
-        let repulsive = if sys.interacting {
-            let mut s = 0.;
-            for (i, particle) in sys.particles.iter().enumerate() {
-                for other in sys.particles[i + 1..].iter() {
-                    // Dimensions should always match, can safely unwrap
-                    s += particle.distance_to(other).unwrap();
-                }
+    /// Returns the total potential energy, and the part of it that comes from the interaction
+    fn potential<D: Dim, W: TrialWaveFunction>(&self, sys: &System<D, W>) -> (f64, f64)
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let external_potential: f64 = sys.particles.iter().map(|x| self.external.evaluate(x)).sum();
+        let interaction = if sys.interacting { total_interaction(self.interaction.as_ref(), &sys.particles) } else { 0. };
+
+        (external_potential + interaction, interaction)
+    }
+
+    /// Calculates the local energy of `sys`. Returns the total, the kinetic and the interaction
+    /// energy, in that order.
+    pub fn energy<D: Dim, W: TrialWaveFunction>(&self, sys: &System<D, W>) -> Result<(f64, f64, f64), String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let kinetic = Self::kinetic(sys)?;
+        let (potential, interaction) = self.potential(sys);
+        Ok((kinetic + potential, kinetic, interaction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Const;
+    use crate::{interaction::Coulomb, WaveFunction};
+
+    #[test]
+    fn test_anisotropic_eigenstates() {
+        // With alpha = 1 the Slater determinant is an eigenstate of the non-interacting system, so
+        // the local energy is the sum of omega_d * (n_d + 1/2) over the occupied orbitals anywhere
+        let wf = WaveFunction { alpha: 1., beta: 0., omega: 1., anisotropy: [1., 2., 1.], jastrow_on: false };
+        let sys = System::<Const<2>>::new(2, 2, wf, false, false, 1., 1).unwrap();
+        assert!((sys.hamiltonian.energy(&sys).unwrap().0 - 3.).abs() < 1e-10);
+
+        let wf = WaveFunction { alpha: 1., beta: 0., omega: 1., anisotropy: [1., 1., 2.], jastrow_on: false };
+        for seed in 0..5 {
+            let sys = System::<Const<8>>::new(8, 3, wf.clone(), false, false, 1., seed).unwrap();
+            assert!((sys.hamiltonian.energy(&sys).unwrap().0 - 24.).abs() < 1e-8);
+        }
+    }
+
+    #[test]
+    fn test_external_potential() {
+        let wf = WaveFunction { alpha: 0.9, beta: 0., omega: 1., anisotropy: [1.; 3], jastrow_on: false };
+        let mut sys = System::<Const<6>>::new(6, 2, wf, false, false, 1., 1).unwrap();
+        let (energy, kinetic, _) = sys.hamiltonian.energy(&sys).unwrap();
+
+        // The default is the harmonic oscillator, so only the potential energy changes
+        sys.hamiltonian = Hamiltonian::new(|p: &Particle| 0.5 * p.squared_sum() + 1., Coulomb);
+        let (shifted, same_kinetic, _) = sys.hamiltonian.energy(&sys).unwrap();
+        assert_eq!(kinetic, same_kinetic);
+        assert!((shifted - energy - 6.).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_interaction_energy() {
+        let wf = WaveFunction { alpha: 0.9, beta: 0., omega: 1., anisotropy: [1.; 3], jastrow_on: false };
+        let mut sys = System::<Const<6>>::new(6, 2, wf, true, false, 1., 2).unwrap();
+        let (energy, _, interaction) = sys.hamiltonian.energy(&sys).unwrap();
+
+        let mut want = 0.;
+        for i in 0..6 {
+            for j in i + 1..6 {
+                want += 1. / sys.particles[i].distance_to(&sys.particles[j]).unwrap();
             }
-            1. / s
-        } else { 0. };
-
-        0.5 * sys.wf.omega.powf(2.0) * external_potential + repulsive
-    }
-
-    /// Calculates the energy of a system of `particles` described by `wf`.
-    /// If `non_interacting` is `true`, will calculate the non-interacting energy (unused for now).
-    pub fn energy<const N: usize>(sys: &System<N>) -> Result<(f64, f64), String> {
-        if N == 200 && !sys.num_laplace && !sys.wf.jastrow_on {
-            let distance = sys.particles[0].distance_to(&sys.particles[1])?;
-            let r1 = sys.particles[0].squared_sum();
-            let r2 = sys.particles[1].squared_sum();
-            Ok((2. * sys.wf.alpha * sys.wf.omega + 1. / distance
-               + 0.5 * sys.wf.omega.powi(2) * (1. - sys.wf.alpha.powi(2)) * (r1 + r2), 1.))
-        } else if N == 200 && !sys.num_laplace {
-            let a = 1.; // Hard-coding value of a
-            let distance = sys.particles[0].distance_to(&sys.particles[1])?;
-            let energy = 2. * sys.wf.alpha * sys.wf.omega + 0.5
-                  + sys.wf.omega.powi(2) * (1. - sys.wf.alpha.powi(2)) * (sys.particles[0].squared_sum() + sys.particles[1].squared_sum())
-                  - a / (1. + sys.wf.beta * distance).powi(2) * (- sys.wf.alpha * sys.wf.omega * distance
-                                                                 + a / (1. + sys.wf.beta * distance).powi(2)
-                                                                 + (1. - sys.wf.beta * distance) / (distance * (1. + sys.wf.beta * distance)))
-                  + 1. / distance;
-            Ok((energy, 1.))
-        } else {
-            let kinetic = Self::kinetic(sys)?;
-            Ok((kinetic + Self::potential(sys), kinetic))
         }
+        assert!((interaction - want).abs() < 1e-10);
+
+        sys.interacting = false;
+        let (non_interacting, _, interaction) = sys.hamiltonian.energy(&sys).unwrap();
+        assert_eq!(interaction, 0.);
+        assert!((energy - non_interacting - want).abs() < 1e-10);
     }
 }
@@ -1,81 +1,52 @@
 pub struct Hermite;
 
-const C: [f64; 36] = [
-    1., 0., 2., -2., 0., 4., 0., -12., 0., 8., 12., 0., -48., 0., 16., 0., 120., 0., -160., 0.,
-    32., -120., 0., 720., 0., -480., 0., 64., 0., -1680., 0., 3360., 0., -1344., 0., 128.,
-];
-
 impl Hermite {
-    /// Evaluates the Hermite polynomial of order n.
-    /// Supports only orders 0-7, the first four are hard-coded for efficiency.
+    /// Evaluates the (physicists') Hermite polynomial of order n.
+    /// The first four are hard-coded for efficiency, the rest use the recurrence relation
+    /// H_{n+1}(x) = 2x H_n(x) - 2n H_{n-1}(x).
     pub fn evaluate(x: f64, n: usize) -> Result<f64, String> {
-        if n > 7 {
-            return Err("This function does not support orders higher than 7.".to_owned());
-        }
-
         let result = match n {
             0 => 1.,
             1 => 2. * x,
             2 => 4. * x.powi(2) - 2.,
             3 => 8. * x.powi(3) - 12. * x,
             _ => {
-                let m = (1..=n).sum::<usize>();
-                (0..=n).map(|i| C[i + m] * x.powi(i as i32)).sum()
+                let mut prev = 4. * x.powi(2) - 2.;
+                let mut current = 8. * x.powi(3) - 12. * x;
+                for k in 3..n {
+                    let next = 2. * x * current - 2. * k as f64 * prev;
+                    prev = current;
+                    current = next;
+                }
+                current
             }
         };
 
         Ok(result)
     }
 
-    /// Evaluates the derivative of the Hermite polynomial of order n.
-    /// Supports only orders 0-7, the first four are hard-coded for efficiency.
+    /// Evaluates the derivative of the Hermite polynomial of order n, using H'_n = 2n H_{n-1}.
     pub fn derivative(x: f64, n: usize) -> Result<f64, String> {
-        let result = match n {
+        Ok(match n {
             0 => 0.,
-            1 => 2.,
-            2 => 8. * x,
-            3 => 24. * x.powi(2) - 12.0,
-            _ => {
-                // Use a two point approximation of the derivative for efficiency
-                let h = 0.00000001;
-                (Self::evaluate(x + h, n)? - Self::evaluate(x - h, n)?) / (2. * h)
-            }
-        };
-
-        Ok(result)
+            _ => 2. * n as f64 * Self::evaluate(x, n - 1)?,
+        })
     }
 
-    /// Evaluates the second derivative of the Hermite polynomial of order n.
-    /// Supports only orders 0-7, the first four are hard-coded for efficiency.
+    /// Evaluates the second derivative of the Hermite polynomial of order n, using
+    /// H''_n = 4n(n - 1) H_{n-2}.
     pub fn double_derivative(x: f64, n: usize) -> Result<f64, String> {
-        let result = match n {
-            0 => 0.,
-            1 => 0.,
-            2 => 8.,
-            3 => 48. * x,
-            _ => {
-                // Use a three point approximation of the double derivative for efficiency
-                let h = 0.00000001;
-                (Self::evaluate(x + h, n)? - 2. * Self::evaluate(x, n)? + Self::evaluate(x - h, n)?)
-                    / h.powi(2)
-            }
-        };
-
-        Ok(result)
+        Ok(match n {
+            0 | 1 => 0.,
+            _ => 4. * (n * (n - 1)) as f64 * Self::evaluate(x, n - 2)?,
+        })
     }
 
-    /// Evaluates the derivative of the Hermite polynomial of order n, with regards to alpha.
-    /// Supports only orders 0-3.
+    /// Evaluates the derivative of the Hermite polynomial of order n evaluated at sqrt(omega *
+    /// alpha) * x, with regards to alpha.
     pub fn derivative_alpha(n: usize, x: f64, omega: f64, alpha: f64) -> Result<f64, String> {
-        let sqrt_omega_alpha: f64 = (omega * alpha).powf(0.5);
-        Ok(match n {
-            0 => 0.,
-            1 => x * (omega / alpha).sqrt(),
-            2 => 4. * x * x * omega,
-            3 => 12. * x * x * x * omega * sqrt_omega_alpha - 6.0 * (omega / alpha).sqrt(),
-            4 => 32. * alpha * omega.powi(2) * x.powi(4) - 48. * omega * x.powi(2),
-            _ => return Err("derivative_alpha supports only orders up to 4".to_owned())
-        })
+        let sqrt_omega_alpha: f64 = (omega * alpha).sqrt();
+        Ok(Self::derivative(sqrt_omega_alpha * x, n)? * 0.5 * x * (omega / alpha).sqrt())
     }
 }
 
@@ -97,12 +68,36 @@ mod tests {
     }
 
     #[test]
-    fn test_returns_error() {
-        match Hermite::evaluate(1., 8) {
-            Ok(_) => {
-                panic!("Hermite::evalute did not error correctly when the order was too high.")
+    fn test_high_orders() {
+        // H_8(x) = 256x^8 - 3584x^6 + 13440x^4 - 13440x^2 + 1680
+        let x: f64 = 0.7;
+        let want = 256. * x.powi(8) - 3584. * x.powi(6) + 13440. * x.powi(4) - 13440. * x.powi(2) + 1680.;
+        let got = Hermite::evaluate(x, 8).unwrap();
+        assert!((want - got).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_derivatives() {
+        let h = 1e-5;
+        for n in 0..10 {
+            for &x in &[-1.3, 0.2, 0.9] {
+                let numerical = (Hermite::evaluate(x + h, n).unwrap() - Hermite::evaluate(x - h, n).unwrap()) / (2. * h);
+                let got = Hermite::derivative(x, n).unwrap();
+                assert!((numerical - got).abs() < 1e-6 * numerical.abs().max(1.));
+
+                let numerical = (Hermite::derivative(x + h, n).unwrap() - Hermite::derivative(x - h, n).unwrap()) / (2. * h);
+                let got = Hermite::double_derivative(x, n).unwrap();
+                assert!((numerical - got).abs() < 1e-6 * numerical.abs().max(1.));
             }
-            Err(_) => {}
         }
     }
+
+    #[test]
+    fn test_derivative_alpha() {
+        // H_4(sqrt(omega * alpha) x) differentiated by alpha is 32 alpha omega^2 x^4 - 48 omega x^2
+        let (x, omega, alpha): (f64, f64, f64) = (0.8, 0.5, 1.2);
+        let want = 32. * alpha * omega.powi(2) * x.powi(4) - 48. * omega * x.powi(2);
+        let got = Hermite::derivative_alpha(4, x, omega, alpha).unwrap();
+        assert!((want - got).abs() < 1e-12);
+    }
 }
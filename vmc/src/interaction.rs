@@ -0,0 +1,138 @@
+use crate::Particle;
+
+use std::f64::consts::PI;
+
+/// Interaction between a pair of particles, depending only on the distance between them. Anything
+/// that maps a distance and a dimensionality to an energy, including closures, can be used as one.
+pub trait Interaction: Send + Sync {
+    /// Returns the interaction energy of two particles a distance `r` apart in `dim` dimensions
+    fn evaluate(&self, r: f64, dim: usize) -> f64;
+}
+
+impl<F: Fn(f64, usize) -> f64 + Send + Sync> Interaction for F {
+    fn evaluate(&self, r: f64, dim: usize) -> f64 {
+        self(r, dim)
+    }
+}
+
+/// The Coulomb repulsion between two unit charges, 1 / r.
+#[derive(Clone, Debug)]
+pub struct Coulomb;
+
+impl Interaction for Coulomb {
+    fn evaluate(&self, r: f64, _: usize) -> f64 {
+        1. / r
+    }
+}
+
+/// Coulomb repulsion softened at short distances, 1 / sqrt(r^2 + epsilon^2). Often used for
+/// quantum dots, and in one dimension, where the bare Coulomb repulsion is too singular.
+#[derive(Clone, Debug)]
+pub struct SoftenedCoulomb {
+    pub epsilon: f64,
+}
+
+impl Interaction for SoftenedCoulomb {
+    fn evaluate(&self, r: f64, _: usize) -> f64 {
+        1. / (r * r + self.epsilon * self.epsilon).sqrt()
+    }
+}
+
+/// Coulomb repulsion screened by the surrounding medium, exp(-screening * r) / r.
+#[derive(Clone, Debug)]
+pub struct Yukawa {
+    pub screening: f64,
+}
+
+impl Interaction for Yukawa {
+    fn evaluate(&self, r: f64, _: usize) -> f64 {
+        (-self.screening * r).exp() / r
+    }
+}
+
+/// Contact interaction of the given `strength`, smeared out to a normalized Gaussian of standard
+/// deviation `width` so that it can be sampled.
+#[derive(Clone, Debug)]
+pub struct Contact {
+    pub strength: f64,
+    pub width: f64,
+}
+
+impl Interaction for Contact {
+    fn evaluate(&self, r: f64, dim: usize) -> f64 {
+        let variance = self.width * self.width;
+        self.strength * (-0.5 * r * r / variance).exp() / (2. * PI * variance).powf(0.5 * dim as f64)
+    }
+}
+
+/// Interaction energy of a configuration of particles.
+#[derive(Clone, Debug, Default)]
+pub struct InteractionEnergy {
+    /// Sum over every pair
+    pub total: f64,
+    /// Contribution of every pair (i, j) with i < j
+    pub pairs: Vec<(usize, usize, f64)>,
+}
+
+/// Calls `f` with the indices i < j and the interaction energy of every pair of `particles`.
+fn for_each_pair(interaction: &dyn Interaction, particles: &[Particle], mut f: impl FnMut(usize, usize, f64)) {
+    for (i, particle) in particles.iter().enumerate() {
+        for (j, other) in particles.iter().enumerate().skip(i + 1) {
+            // Dimensions should always match, can safely unwrap
+            f(i, j, interaction.evaluate(particle.distance_to(other).unwrap(), particle.dim));
+        }
+    }
+}
+
+/// Returns the interaction energy of every pair of `particles`, and the total.
+pub fn interaction_energy(interaction: &dyn Interaction, particles: &[Particle]) -> InteractionEnergy {
+    let mut energy = InteractionEnergy::default();
+    for_each_pair(interaction, particles, |i, j, pair| {
+        energy.total += pair;
+        energy.pairs.push((i, j, pair));
+    });
+    energy
+}
+
+/// Returns the total interaction energy of `particles`. Unlike `interaction_energy` it does not
+/// keep the pairs, since it is called for every local energy.
+pub fn total_interaction(interaction: &dyn Interaction, particles: &[Particle]) -> f64 {
+    let mut total = 0.;
+    for_each_pair(interaction, particles, |_, _, pair| total += pair);
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Vector;
+
+    #[test]
+    fn test_pairwise_sum() {
+        let particles: Vec<Particle> = [0., 1., 3.].iter().map(|&x| Particle::from_vector(Vector::D2(x, 0.))).collect();
+        let energy = interaction_energy(&Coulomb, &particles);
+        assert!((energy.total - (1. + 1. / 3. + 1. / 2.)).abs() < 1e-12);
+        assert_eq!(energy.pairs.len(), 3);
+        assert_eq!((energy.pairs[1].0, energy.pairs[1].1), (0, 2));
+        assert!((energy.pairs[1].2 - 1. / 3.).abs() < 1e-12);
+        assert_eq!(total_interaction(&Coulomb, &particles), energy.total);
+    }
+
+    #[test]
+    fn test_interactions() {
+        let tol = 1e-12;
+        for &r in [0.1, 1., 2.5].iter() {
+            assert!((SoftenedCoulomb { epsilon: 0. }.evaluate(r, 2) - Coulomb.evaluate(r, 2)).abs() < tol);
+            assert!(SoftenedCoulomb { epsilon: 0.5 }.evaluate(r, 2) < Coulomb.evaluate(r, 2));
+            assert!((Yukawa { screening: 0. }.evaluate(r, 2) - Coulomb.evaluate(r, 2)).abs() < tol);
+            assert!(Yukawa { screening: 1. }.evaluate(r, 2) < Coulomb.evaluate(r, 2));
+        }
+        assert_eq!(SoftenedCoulomb { epsilon: 0.5 }.evaluate(0., 1), 2.);
+
+        // In one dimension the Gaussian integrates to the strength
+        let contact = Contact { strength: 2., width: 0.1 };
+        let h = 0.001;
+        let integral: f64 = (-1000..=1000).map(|i| contact.evaluate((i as f64 * h).abs(), 1) * h).sum();
+        assert!((integral - 2.).abs() < 1e-6);
+    }
+}
@@ -1,31 +1,67 @@
+mod basis;
+mod blocking;
+mod cli;
+mod config;
+mod correlated;
 mod hamiltonian;
 mod hermite;
+mod interaction;
 mod metropolis;
 mod montecarlo;
+mod optimizer;
+mod parameters;
 mod particle;
+mod potential;
+mod recorder;
 mod run;
 mod system;
 mod threadpool;
+mod trial;
 mod utils;
+mod validation;
+mod variational;
 mod vector;
 mod wavefunction;
 
+pub use basis::{QuantumNumbers, occupation, quantum_numbers};
+pub use blocking::{block, Blocker, BlockingResult};
+pub use correlated::{Reweighted, Walkers};
 pub use hamiltonian::Hamiltonian;
 pub use hermite::Hermite;
+pub use interaction::{interaction_energy, total_interaction, Contact, Coulomb, Interaction, InteractionEnergy, SoftenedCoulomb, Yukawa};
 pub use metropolis::{BruteForceMetropolis, ImportanceMetropolis, Metropolis};
-pub use montecarlo::monte_carlo;
+pub use montecarlo::{monte_carlo, monte_carlo_recorded};
+pub use optimizer::{minimize_correlated, optimize, Adam, ConvergenceLog, CorrelatedSampling, GradientDescent, Iteration, LinearMethod, LineSearch, Momentum, Objective, Optimized, Optimizer, Stopping, StochasticReconfiguration};
 pub use particle::Particle;
-pub use system::System;
+pub use potential::{CoulombCenter, DoubleWell, ExternalPotential, FiniteWell, HarmonicOscillator};
+pub use recorder::Recorder;
+pub use system::{Refresh, System};
 pub use threadpool::ThreadPool;
-pub use utils::{Spin, a, det};
+pub use trial::{GaussianProduct, HydrogenOrbital, TrialWaveFunction};
+pub use utils::{Spin, a, log_det};
+pub use validation::{check, check_random, Discrepancies};
+pub use variational::{ParameterDerivatives, Variational};
 pub use vector::Vector;
-pub use wavefunction::{WaveFunction, QUANTUM_NUMBERS};
+pub use wavefunction::{SlaterMatrix, SlaterState, WaveFunction};
+
+use cli::Command;
+use structopt::StructOpt;
 
 fn main() {
-    // run::simple(); 
-    // run::sgd(true);
-    run::sgd_omega(true);
-    // run::multiple();
-    // run::onebody();
-    //run::performance();
+    let result = match Command::from_args() {
+        Command::Simple { overrides, record_every } => run::simple(&overrides, record_every),
+        Command::Multiple { overrides, omegas, alphas, betas } => run::multiple(&overrides, &omegas, &alphas, &betas),
+        Command::Scan { overrides, omegas, alphas, betas, threads } => run::scan(&overrides, omegas, alphas, betas, threads),
+        Command::Sgd { overrides, sgd, start_alphas, start_betas } => run::sgd(&overrides, &sgd, &start_alphas, &start_betas),
+        Command::SgdOmega { overrides, sgd, omegas } => run::sgd_omega(&overrides, &sgd, &omegas),
+        Command::Reweight { overrides, alphas, betas } => run::reweight(&overrides, &alphas, &betas),
+        Command::Onebody { overrides } => run::onebody(&overrides),
+        Command::Performance { overrides } => run::performance(&overrides),
+        Command::Check { overrides, configurations, step, tolerance } => run::check(&overrides, configurations, step, tolerance),
+    };
+
+    if let Err(why) = result {
+        eprintln!("Error: {}", why);
+        std::process::exit(1);
+    }
 }
@@ -1,59 +1,61 @@
-use crate::{montecarlo::SampledValues, Hamiltonian, Particle, System};
-use nalgebra::SMatrix;
+use crate::{montecarlo::SampledValues, system::TIME_STEP, ParameterDerivatives, Particle, System, TrialWaveFunction};
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim};
 use rand::distributions::{Distribution, Uniform};
-use rand::thread_rng;
+use rand::Rng;
 use std::collections::HashMap;
 
 /// Trait for Metropolis samplers.
 pub trait Metropolis {
     fn new(step_size: f64) -> Self;
-    fn step<const N: usize>(
+    fn step<D: Dim, W: TrialWaveFunction>(
         &mut self,
-        sys: &mut System<N>,
-    ) -> Result<Option<SampledValues>, String>;
-
-    fn hastings_check(acceptance_factor: f64) -> bool {
-        if acceptance_factor >= 1. {
+        sys: &mut System<D, W>,
+    ) -> Result<Option<SampledValues>, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>;
+
+    /// Accepts a move with probability min(1, exp(`log_acceptance`)). The acceptance is given as a
+    /// logarithm so that ratios of wavefunctions far apart never overflow or underflow.
+    fn hastings_check<R: Rng>(rng: &mut R, log_acceptance: f64) -> bool {
+        if log_acceptance >= 0. {
             true
         } else {
-            let mut rng = thread_rng();
-            let uniform = Uniform::new(0., 1.);
-            uniform.sample(&mut rng) < acceptance_factor
+            let uniform = Uniform::new(0f64, 1.);
+            uniform.sample(rng).ln() < log_acceptance
         }
     }
 
-    fn sample<const N: usize>(sys: &mut System<N>) -> Result<SampledValues, String> {
-        let (energy, kinetic) = Hamiltonian::energy(&sys)?;
-        let wf_deriv_alpha = sys.wf.gradient_alpha(&sys.particles)?;
-        let wf_deriv_beta = sys.wf.gradient_beta(&sys.particles)?;
+    fn sample<D: Dim, W: TrialWaveFunction>(sys: &mut System<D, W>) -> Result<SampledValues, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let (energy, kinetic, interaction) = sys.hamiltonian.energy(sys)?;
+        let derivatives = sys.wf.log_derivatives(&sys.particles, &sys.state)?;
 
         let mut map = HashMap::new();
         map.insert("energy".to_string(), energy);
         map.insert("kinetic".to_string(), kinetic);
+        map.insert("interaction".to_string(), interaction);
         map.insert("energy_sqrd".to_string(), energy.powi(2));
-        map.insert("wf_deriv_alpha".to_string(), wf_deriv_alpha);
-        map.insert("wf_deriv_alpha_times_energy".to_string(), wf_deriv_alpha * energy);
-        map.insert("wf_deriv_beta".to_string(), wf_deriv_beta);
-        map.insert("wf_deriv_beta_times_energy".to_string(), wf_deriv_beta * energy);
         if sys.particles.len() == 2 {
             let distance = sys.particles[0].distance_to(&sys.particles[1])?;
             map.insert("distance".to_string(), distance);
-            let wf_squared = sys.wf.evaluate::<N>(&sys.particles)?;
+            let wf_squared = sys.wf.evaluate(&sys.particles)?;
             map.insert("wf_squared".to_string(), wf_squared);
         }
-        Ok(SampledValues { map, accepted_steps: 0 })
+        let mut derivatives = ParameterDerivatives::sample(derivatives, energy);
+        if sys.energy_derivatives {
+            derivatives = derivatives.with_energy_derivatives(energy, sys.local_energy_derivatives()?);
+        }
+        Ok(SampledValues { map, derivatives, ..SampledValues::new() })
     }
 
-    fn greens(x: &Particle, y: &Particle, n: usize) -> Result<f64, String> {
+    /// Logarithm of the Green's function of moving a particle from `y` to `x` in one time step of
+    /// the Langevin equation, leaving out the normalization since it cancels in the acceptance
+    fn log_greens(x: &Particle, y: &Particle) -> Result<f64, String> {
         let diffusion = 0.5;
-        let time_step = 0.001;
-        let factor = 1. / (4. * diffusion * time_step);
-        let yx = y.position - x.position - x.qforce.scale(diffusion * time_step);
-        let xy = x.position - y.position - y.qforce.scale(diffusion * time_step);
-        let vec_sum = x.position - y.position - y.qforce.scale(0.0025);
-        // Ignoring denominator of Greens since it cancels later
-        // Ok(((- xy.inner(xy)? + yx.inner(yx)?) * factor + n as f64 - 1.).exp())
-        Ok((-vec_sum.inner(vec_sum)? * factor).exp())
+        let drift = x.position - y.position - y.qforce.scale(diffusion * TIME_STEP);
+        Ok(-drift.inner(drift)? / (4. * diffusion * TIME_STEP))
     }
 }
 
@@ -66,33 +68,18 @@ impl Metropolis for BruteForceMetropolis {
         Self { step_size }
     }
 
-    fn step<const N: usize>(
+    fn step<D: Dim, W: TrialWaveFunction>(
         &mut self,
-        sys: &mut System<N>,
-    ) -> Result<Option<SampledValues>, String> {
+        sys: &mut System<D, W>,
+    ) -> Result<Option<SampledValues>, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
         let (new_particles, p) = sys.random_particle_change(self.step_size);
-        let mut new_inverse: SMatrix<f64, N, N> = SMatrix::repeat(0.);
-
-        let acceptance_factor = match N {
-            2 => {
-                let wf_old = sys.wf.evaluate::<N>(&sys.particles)?;
-                let wf_new = sys.wf.evaluate::<N>(&new_particles)?;
-                wf_new.powi(2) / wf_old.powi(2)
-            }
-            _ => {
-                new_inverse = sys.next_slater_inverse(&new_particles, p)?;
-                if sys.wf.jastrow_on {
-                    sys.next_slater_ratio(p, &new_inverse)
-                } else {
-                    sys.next_slater_ratio(p, &new_inverse) * sys.next_jastrow_ratio(p, &new_particles)
-                }
-            }
-        };
-
-        if Self::hastings_check(acceptance_factor) {
-            sys.particles = new_particles;
-            sys.slater_inverse = new_inverse;
-            sys.slater_ratio = acceptance_factor;
+        let log_acceptance = 2. * sys.log_ratio(p, &new_particles)?;
+
+        if Self::hastings_check(&mut sys.rng, log_acceptance) {
+            sys.accept_move(p, new_particles)?;
             Ok(Some(Self::sample(sys)?))
         } else {
             Ok(None)
@@ -107,41 +94,23 @@ impl Metropolis for ImportanceMetropolis {
         Self
     }
 
-    fn step<const N: usize>(
+    fn step<D: Dim, W: TrialWaveFunction>(
         &mut self,
-        sys: &mut System<N>,
-    ) -> Result<Option<SampledValues>, String> {
-        let mut new_inverse: SMatrix<f64, N, N> = SMatrix::repeat(0.);
-
+        sys: &mut System<D, W>,
+    ) -> Result<Option<SampledValues>, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
         // Make a step
         let (new_particles, p) = sys.quantum_force_particle_change()?;
-        let n = sys.particles.len();
 
         // Calculate the acceptance factor
-        let greens_factor = Self::greens(&sys.particles[p], &new_particles[p], n)?
-            / Self::greens(&new_particles[p], &sys.particles[p], n)?;
-
-        let acceptance_factor = match N {
-            2 => {
-                let wf_old = sys.wf.evaluate::<N>(&sys.particles)?;
-                let wf_new = sys.wf.evaluate::<N>(&new_particles)?;
-                //println!("GF: {:.16} || wfN: {:.16} || wfO: {:.16}", greens_factor, wf_new, wf_old);
-                greens_factor * wf_new.powi(2) / wf_old.powi(2)
-            }
-            _ => {
-                new_inverse = sys.next_slater_inverse(&new_particles, p)?;
-                greens_factor * if !sys.wf.jastrow_on {
-                    sys.next_slater_ratio(p, &new_inverse)
-                } else {
-                    sys.next_slater_ratio(p, &new_inverse) * sys.next_jastrow_ratio(p, &new_particles)
-                }
-            }
-        };
-
-        if Self::hastings_check(acceptance_factor) {
-            sys.particles = new_particles;
-            sys.slater_inverse = new_inverse;
-            sys.slater_ratio = acceptance_factor;
+        let log_greens = Self::log_greens(&sys.particles[p], &new_particles[p])?
+            - Self::log_greens(&new_particles[p], &sys.particles[p])?;
+        let log_acceptance = log_greens + 2. * sys.log_ratio(p, &new_particles)?;
+
+        if Self::hastings_check(&mut sys.rng, log_acceptance) {
+            sys.accept_move(p, new_particles)?;
             Ok(Some(Self::sample(sys)?))
         } else {
             Ok(None)
@@ -153,12 +122,14 @@ impl Metropolis for ImportanceMetropolis {
 mod tests {
     use super::*;
     use crate::Vector;
+    use rand::{rngs::StdRng, SeedableRng};
 
     #[test]
     fn test_hastings_check() {
-        assert!(BruteForceMetropolis::hastings_check(1.)); //Panics if it returns false
-        assert!(BruteForceMetropolis::hastings_check(2.));
-        assert!(!BruteForceMetropolis::hastings_check(0.)) //Panics if it returns true
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(BruteForceMetropolis::hastings_check(&mut rng, 0.)); //Panics if it returns false
+        assert!(BruteForceMetropolis::hastings_check(&mut rng, 2.));
+        assert!(!BruteForceMetropolis::hastings_check(&mut rng, f64::NEG_INFINITY)) //Panics if it returns true
     }
 
     #[test]
@@ -167,8 +138,9 @@ mod tests {
         let mut pnew = Particle::from_vector(Vector::D2(0.011, 0.011));
         pnew.qforce += Vector::D2(0.2, 0.2);
 
-        let want = 0.999000499833375;
+        // |x - y|^2 / (4 D dt) with D = 0.5 and dt = 0.005, as pold has no quantum force
+        let want = -2e-6 / 0.01;
 
-        assert_eq!(BruteForceMetropolis::greens(&pnew, &pold, 2).unwrap(), want);
+        assert!((BruteForceMetropolis::log_greens(&pnew, &pold).unwrap() - want).abs() < 1e-15);
     }
 }
@@ -1,13 +1,19 @@
-use crate::{Metropolis, System, utils};
+use crate::{Metropolis, ParameterDerivatives, Recorder, System, TrialWaveFunction, blocking::{Blocker, BlockingResult}};
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim};
 use std::collections::HashMap;
-use std::io::Write;
-use std::time::Instant;
 
 /// Collection of values that are integrated over
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct SampledValues {
     pub map: HashMap<String, f64>,
     pub accepted_steps: usize,
+    /// Logarithmic derivatives of the wavefunction with regards to every variational parameter
+    pub derivatives: ParameterDerivatives,
+    /// Blocking analysis of each value in `map`. Only filled in by `monte_carlo`.
+    pub blocking: HashMap<String, BlockingResult>,
+    /// Blocking analysis of the derivative of the energy with regards to each variational
+    /// parameter. Only filled in by `monte_carlo`.
+    pub gradient_blocking: Vec<BlockingResult>,
 }
 
 impl SampledValues {
@@ -15,6 +21,29 @@ impl SampledValues {
         SampledValues {
             map: HashMap::new(),
             accepted_steps: 0,
+            derivatives: ParameterDerivatives::default(),
+            blocking: HashMap::new(),
+            gradient_blocking: vec![],
+        }
+    }
+
+    /// Returns the correlation-corrected standard error of the mean of `key`
+    pub fn error(&self, key: &str) -> Option<f64> {
+        self.blocking.get(key).map(|b| b.error)
+    }
+
+    /// Returns the estimated autocorrelation time of `key`, in number of Monte Carlo cycles
+    pub fn autocorrelation_time(&self, key: &str) -> Option<f64> {
+        self.blocking.get(key).map(|b| b.autocorrelation_time)
+    }
+
+    /// Returns the correlation-corrected standard error of the derivative of the energy with
+    /// regards to each variational parameter, if there were enough samples to estimate it
+    pub fn gradient_error(&self) -> Option<Vec<f64>> {
+        if self.gradient_blocking.len() == self.derivatives.mean.len() {
+            Some(self.gradient_blocking.iter().map(|b| b.error).collect())
+        } else {
+            None
         }
     }
 
@@ -22,65 +51,210 @@ impl SampledValues {
         for (key, val) in self.map.iter_mut() {
             *val += dvals.map[key];
         }
+        self.derivatives.add(&dvals.derivatives);
     }
 
     pub fn divide_f64(&mut self, factor: f64) {
         for val in self.map.values_mut() {
             *val /= factor;
         }
+        self.derivatives.divide(factor);
     }
 }
 
 /// Does Monte Carlo integration over the WaveFunction of a System, using a given Metropolis
 /// algorithm.
-pub fn monte_carlo<T: Metropolis, const N: usize>(
+pub fn monte_carlo<T: Metropolis, D: Dim, W: TrialWaveFunction>(
+    n: usize,
+    sys: &mut System<D, W>,
+    metro: &mut T,
+) -> Result<SampledValues, String>
+where
+    DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+{
+    integrate(n, sys, metro, None)
+}
+
+/// Does the same integration as `monte_carlo`, but also records the sampled values of every
+/// cycle (including the equilibration cycles) to `recorder`.
+pub fn monte_carlo_recorded<T: Metropolis, D: Dim, W: TrialWaveFunction>(
     n: usize,
-    sys: &mut System<N>,
+    sys: &mut System<D, W>,
     metro: &mut T,
-) -> Result<SampledValues, String> {
+    recorder: &mut Recorder,
+) -> Result<SampledValues, String>
+where
+    DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+{
+    let result = integrate(n, sys, metro, Some(recorder))?;
+    recorder.flush()?;
+    Ok(result)
+}
+
+fn integrate<T: Metropolis, D: Dim, W: TrialWaveFunction>(
+    n: usize,
+    sys: &mut System<D, W>,
+    metro: &mut T,
+    mut recorder: Option<&mut Recorder>,
+) -> Result<SampledValues, String>
+where
+    DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+{
     let pre_steps = n / 4;
     let mut result = SampledValues::new();
-
-    //Save to file
-    /*
-    let mut path = utils::find_cargo_root().unwrap();
-    path.push("data");
-    utils::create_dir(&path);
-    let metro_type = std::any::type_name::<T>().split("::").last().unwrap();
-    path.push(format!("E_vs_MCs_{}.csv", metro_type));
-    let mut f = utils::create_file(&path);
-    f.write_all("MCcycle,energy[au],time[s]\n".as_bytes()).expect("Unable to write data");
-    let start = Instant::now();
-    */
+    // Means of the energy and the parameter derivatives during the equilibration
+    let mut warmup = (0., ParameterDerivatives::default(), 0);
 
     // Run a couple of steps to get the system into equilibrium
-    for _ in 0..pre_steps {
-        match metro.step(sys)? {
-            Some(vals) => result = vals,
-            None => {}
+    for i in 0..pre_steps {
+        let accepted = match metro.step(sys)? {
+            Some(vals) => {
+                result = vals;
+                true
+            }
+            None => false,
+        };
+        if let Some(energy) = result.map.get("energy") {
+            warmup.0 += energy;
+            warmup.1.add(&result.derivatives);
+            warmup.2 += 1;
+        }
+        // Nothing to record before the first accepted step
+        if let (Some(recorder), false) = (recorder.as_deref_mut(), result.map.is_empty()) {
+            recorder.record(i, false, accepted, &result)?;
         }
     }
 
+    // Every sampled value is also blocked, to get an error estimate that accounts for correlations
+    let mut blockers: HashMap<String, Blocker> = result.map.keys()
+        .map(|key| (key.clone(), Blocker::new()))
+        .collect();
+
+    // The energy gradient 2 (<O_k E> - <O_k> <E>) is not a mean of the samples, so it is blocked
+    // linearized around the means from the equilibration, as the mean of 2 (O_k - <O_k>) (E - <E>)
+    let (warmup_energy, mut warmup_derivatives, warmup_cycles) = warmup;
+    if warmup_cycles > 0 {
+        warmup_derivatives.divide(warmup_cycles as f64);
+    }
+    let warmup_energy = warmup_energy / warmup_cycles.max(1) as f64;
+    let mut gradient_blockers: Vec<Blocker> = vec![];
+
     // Store the previous values to add if Metropolis step is rejected
     let mut prev_dvals = result.clone();
     for i in 0..n {
-        match metro.step(sys)? {
+        let accepted = match metro.step(sys)? {
             Some(dvals) => {
-                result.add_to_sum(&dvals);
                 result.accepted_steps += 1;
                 prev_dvals = dvals;
-                
-                //Writing to file
-                /* let data = format!("{},{},{}\n",i, result.map.get("energy").unwrap() / (i as f64), start.elapsed().as_millis() as f64 / 1000.);
-                f.write_all(data.as_bytes()).expect("Unable to write data"); */
-            },
-            None => {
-                result.add_to_sum(&prev_dvals);
+                true
+            }
+            None => false,
+        };
+        result.add_to_sum(&prev_dvals);
+        for (key, blocker) in blockers.iter_mut() {
+            blocker.push(prev_dvals.map[key]);
+        }
+        if let Some(energy) = prev_dvals.map.get("energy") {
+            let derivatives = &prev_dvals.derivatives.mean;
+            gradient_blockers.resize(derivatives.len(), Blocker::new());
+            for (k, blocker) in gradient_blockers.iter_mut().enumerate() {
+                let reference = warmup_derivatives.mean.get(k).copied().unwrap_or(0.);
+                blocker.push(2. * (derivatives[k] - reference) * (energy - warmup_energy));
             }
         }
+        if let Some(recorder) = recorder.as_deref_mut() {
+            recorder.record(pre_steps + i, true, accepted, &prev_dvals)?;
+        }
     }
 
     // Divide all values by n to get the mean
     result.divide_f64((n + 1) as f64);
+    result.blocking = blockers.iter()
+        .filter_map(|(key, blocker)| Some((key.clone(), blocker.analyze()?)))
+        .collect();
+    result.gradient_blocking = gradient_blockers.iter()
+        .map(|blocker| blocker.analyze())
+        .collect::<Option<Vec<_>>>()
+        .unwrap_or_default();
     Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Const;
+    use crate::{BruteForceMetropolis, GaussianProduct, ImportanceMetropolis, WaveFunction};
+
+    fn run<T: Metropolis>(seed: u64, recorder: Option<&mut Recorder>) -> SampledValues {
+        let wf = WaveFunction { alpha: 0.98, beta: 0.43, omega: 1., anisotropy: [1.; 3], jastrow_on: true };
+        let mut system: System<Const<2>> = System::new(2, 2, wf, true, false, 0.1, seed).unwrap();
+        let mut metro = T::new(0.5);
+        match recorder {
+            Some(recorder) => monte_carlo_recorded(2000, &mut system, &mut metro, recorder).unwrap(),
+            None => monte_carlo(2000, &mut system, &mut metro).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_seeded_runs_are_reproducible() {
+        let first = run::<BruteForceMetropolis>(1, None);
+        let second = run::<BruteForceMetropolis>(1, None);
+        let other = run::<BruteForceMetropolis>(2, None);
+        assert_eq!(first.map["energy"], second.map["energy"]);
+        assert_eq!(first.accepted_steps, second.accepted_steps);
+        assert!(first.map["energy"] != other.map["energy"]);
+
+        let first = run::<ImportanceMetropolis>(1, None);
+        let second = run::<ImportanceMetropolis>(1, None);
+        assert_eq!(first.map["energy"], second.map["energy"]);
+    }
+
+    #[test]
+    fn test_recorded_series_matches_blocking() {
+        let mut recorder = Recorder::memory(1);
+        let vals = run::<BruteForceMetropolis>(3, Some(&mut recorder));
+
+        let equilibrated = recorder.series("equilibrated").unwrap();
+        let energies: Vec<f64> = recorder.series("energy").unwrap().into_iter()
+            .zip(equilibrated)
+            .filter(|(_, eq)| *eq == 1.)
+            .map(|(e, _)| e)
+            .collect();
+        assert_eq!(energies.len(), 2000);
+
+        let blocked = crate::block(&energies).unwrap();
+        assert!((blocked.mean - vals.blocking["energy"].mean).abs() < 1e-12);
+        assert_eq!(blocked.error, vals.error("energy").unwrap());
+    }
+
+    #[test]
+    fn test_parameter_derivatives() {
+        let vals = run::<BruteForceMetropolis>(4, None);
+        assert_eq!(vals.derivatives.mean.len(), 2);
+        let covariance = vals.derivatives.covariance();
+        assert!(covariance[(0, 0)] > 0. && covariance[(1, 1)] > 0.);
+
+        // The exact ground state has a constant local energy, so the energy does not change to
+        // first order in alpha
+        let wf = WaveFunction { alpha: 1., beta: 0., omega: 1., anisotropy: [1.; 3], jastrow_on: false };
+        let mut system: System<Const<6>> = System::new(6, 2, wf, false, false, 1., 5).unwrap();
+        let vals = monte_carlo(2000, &mut system, &mut BruteForceMetropolis::new(0.5)).unwrap();
+        let gradient = vals.derivatives.energy_gradient(vals.map["energy"]);
+        assert_eq!(gradient.len(), 1);
+        assert!(gradient[0].abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_gradient_error() {
+        let wf = GaussianProduct { alpha: 0.7, omega: 1., anisotropy: [1.; 3] };
+        let mut system: System<Const<4>, _> = System::new(4, 2, wf, false, false, 1., 6).unwrap();
+        let vals = monte_carlo(20000, &mut system, &mut BruteForceMetropolis::new(1.)).unwrap();
+        let gradient = vals.derivatives.energy_gradient(vals.map["energy"]);
+        let error = vals.gradient_error().unwrap();
+        assert_eq!(error.len(), 1);
+        // Far from the minimum the gradient stands well out of the noise, and the blocked estimate
+        // agrees with it
+        assert!(error[0] > 0. && gradient[0].abs() > 10. * error[0]);
+        assert!((vals.gradient_blocking[0].mean - gradient[0]).abs() < 3. * error[0]);
+    }
+}
@@ -0,0 +1,754 @@
+// Optimization of the variational parameters. Every iteration `optimize` samples the system with
+// the current parameters, and an `Optimizer` turns the sampled energy and parameter derivatives into
+// the parameters of the next iteration. Since the parameters are a vector (see `Variational`), the
+// optimizers work for any number of them.
+
+use crate::{correlated::Walkers, montecarlo::{self, SampledValues}, utils::create_file, Metropolis, System, TrialWaveFunction};
+use nalgebra::{allocator::Allocator, DMatrix, DVector, DefaultAllocator, Dim};
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    str::FromStr,
+    time::Instant,
+};
+
+/// Turns the values sampled at the current parameters into the parameters of the next iteration.
+pub trait Optimizer {
+    /// Returns the parameters of the next iteration, given the current `parameters` and the values
+    /// sampled with them
+    fn step(&mut self, parameters: &[f64], vals: &SampledValues) -> Result<Vec<f64>, String>;
+
+    /// Whether the optimizer needs the derivatives of the local energy with regards to the
+    /// parameters, see `System::energy_derivatives`
+    fn energy_derivatives(&self) -> bool {
+        false
+    }
+}
+
+/// Returns the mean of `key` in `vals`, or an error if it was not sampled
+fn sampled(vals: &SampledValues, key: &str) -> Result<f64, String> {
+    vals.map.get(key).copied().ok_or_else(|| format!("No {} was sampled, was any step accepted?", key))
+}
+
+/// Plain gradient descent, moving every parameter by `learning_rate` times its energy derivative.
+#[derive(Clone, Debug)]
+pub struct GradientDescent {
+    pub learning_rate: f64,
+}
+
+impl Optimizer for GradientDescent {
+    fn step(&mut self, parameters: &[f64], vals: &SampledValues) -> Result<Vec<f64>, String> {
+        let gradient = vals.derivatives.energy_gradient(sampled(vals, "energy")?);
+        Ok(parameters.iter()
+            .zip(&gradient)
+            .map(|(parameter, derivative)| parameter - self.learning_rate * derivative)
+            .collect())
+    }
+}
+
+/// Stochastic reconfiguration, or natural gradient descent. The energy gradient is preconditioned
+/// with the inverse of the overlap matrix S_kl = <O_k O_l> - <O_k> <O_l>, which measures how much
+/// the wavefunction itself changes with each parameter, so that parameters the wavefunction is
+/// insensitive to take larger steps. The diagonal of S is scaled by 1 + `regularization` to keep
+/// the noisy estimate of S from being nearly singular.
+#[derive(Clone, Debug)]
+pub struct StochasticReconfiguration {
+    /// Fraction of the full step taken every iteration
+    pub time_step: f64,
+    pub regularization: f64,
+}
+
+impl Optimizer for StochasticReconfiguration {
+    fn step(&mut self, parameters: &[f64], vals: &SampledValues) -> Result<Vec<f64>, String> {
+        let gradient = vals.derivatives.energy_gradient(sampled(vals, "energy")?);
+        let mut overlap = vals.derivatives.covariance();
+        for k in 0..overlap.nrows() {
+            overlap[(k, k)] *= 1. + self.regularization;
+        }
+
+        // The generalized force is minus half the gradient
+        let force = DVector::from_iterator(gradient.len(), gradient.iter().map(|derivative| -0.5 * derivative));
+        let step = overlap.cholesky()
+            .ok_or_else(|| "The overlap matrix is singular, try a larger regularization.".to_owned())?
+            .solve(&force);
+        Ok(parameters.iter().zip(step.iter()).map(|(parameter, step)| parameter + self.time_step * step).collect())
+    }
+}
+
+/// Gradient descent with momentum. Every step adds the energy gradient to a velocity that decays
+/// by `momentum` each iteration, which smooths out the noise of the sampled gradient and speeds up
+/// along directions where the gradient keeps pointing the same way.
+#[derive(Clone, Debug)]
+pub struct Momentum {
+    pub learning_rate: f64,
+    pub momentum: f64,
+    velocity: Vec<f64>,
+}
+
+impl Momentum {
+    pub fn new(learning_rate: f64, momentum: f64) -> Self {
+        Momentum { learning_rate, momentum, velocity: vec![] }
+    }
+}
+
+impl Optimizer for Momentum {
+    fn step(&mut self, parameters: &[f64], vals: &SampledValues) -> Result<Vec<f64>, String> {
+        let gradient = vals.derivatives.energy_gradient(sampled(vals, "energy")?);
+        self.velocity.resize(gradient.len(), 0.);
+        for (velocity, derivative) in self.velocity.iter_mut().zip(&gradient) {
+            *velocity = self.momentum * *velocity + self.learning_rate * derivative;
+        }
+        Ok(parameters.iter().zip(&self.velocity).map(|(parameter, velocity)| parameter - velocity).collect())
+    }
+}
+
+/// Adam, which scales the step of each parameter by a running estimate of the magnitude of its
+/// derivative, so that every parameter moves by about `learning_rate` per iteration regardless of
+/// how strongly the energy depends on it. The running means of the gradient and its square decay by
+/// `beta1` and `beta2` each iteration.
+#[derive(Clone, Debug)]
+pub struct Adam {
+    pub learning_rate: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub epsilon: f64,
+    first_moment: Vec<f64>,
+    second_moment: Vec<f64>,
+    steps: i32,
+}
+
+impl Adam {
+    /// Adam with the usual decay rates of 0.9 and 0.999
+    pub fn new(learning_rate: f64) -> Self {
+        Adam {
+            learning_rate,
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+            first_moment: vec![],
+            second_moment: vec![],
+            steps: 0,
+        }
+    }
+}
+
+impl Optimizer for Adam {
+    fn step(&mut self, parameters: &[f64], vals: &SampledValues) -> Result<Vec<f64>, String> {
+        let gradient = vals.derivatives.energy_gradient(sampled(vals, "energy")?);
+        self.first_moment.resize(gradient.len(), 0.);
+        self.second_moment.resize(gradient.len(), 0.);
+        self.steps += 1;
+
+        let mut next = parameters.to_vec();
+        for k in 0..gradient.len() {
+            self.first_moment[k] = self.beta1 * self.first_moment[k] + (1. - self.beta1) * gradient[k];
+            self.second_moment[k] = self.beta2 * self.second_moment[k] + (1. - self.beta2) * gradient[k].powi(2);
+            // Both moments start at zero, which biases them towards zero for the first iterations
+            let first = self.first_moment[k] / (1. - self.beta1.powi(self.steps));
+            let second = self.second_moment[k] / (1. - self.beta2.powi(self.steps));
+            next[k] -= self.learning_rate * first / (second.sqrt() + self.epsilon);
+        }
+        Ok(next)
+    }
+}
+
+/// Gradient descent with a backtracking line search, in the spirit of the "bold driver". As long as
+/// the energy goes down the learning rate grows by `growth` every iteration. When the energy goes
+/// up, the step is taken again from the last parameters that lowered it, with the learning rate
+/// cut by `shrink`.
+#[derive(Clone, Debug)]
+pub struct LineSearch {
+    pub learning_rate: f64,
+    pub growth: f64,
+    pub shrink: f64,
+    /// Parameters, energy and gradient of the lowest energy seen so far
+    best: Option<(Vec<f64>, f64, Vec<f64>)>,
+}
+
+impl LineSearch {
+    pub fn new(learning_rate: f64) -> Self {
+        LineSearch { learning_rate, growth: 1.2, shrink: 0.5, best: None }
+    }
+}
+
+impl Optimizer for LineSearch {
+    fn step(&mut self, parameters: &[f64], vals: &SampledValues) -> Result<Vec<f64>, String> {
+        let energy = sampled(vals, "energy")?;
+        let (from, gradient) = match &self.best {
+            Some((best, best_energy, gradient)) if energy > *best_energy => {
+                self.learning_rate *= self.shrink;
+                (best.clone(), gradient.clone())
+            }
+            _ => {
+                if self.best.is_some() {
+                    self.learning_rate *= self.growth;
+                }
+                let gradient = vals.derivatives.energy_gradient(energy);
+                self.best = Some((parameters.to_vec(), energy, gradient.clone()));
+                (parameters.to_vec(), gradient)
+            }
+        };
+        Ok(from.iter().zip(&gradient).map(|(parameter, derivative)| parameter - self.learning_rate * derivative).collect())
+    }
+}
+
+/// The linear method. The wavefunction is expanded to first order in the parameters, as
+/// psi + sum_k dp_k (O_k - <O_k>) psi, and the steps dp_k are the coefficients of the eigenvector
+/// of lowest eigenvalue of the Hamiltonian in this basis, relative to its overlap matrix. Since the
+/// Hamiltonian includes the derivatives of the local energy this takes Newton-like steps, and
+/// reaches the minimum in far fewer iterations than gradient descent where the energy surface is
+/// flat. Far from the minimum the energy is not quadratic, and the full steps can overshoot,
+/// for instance to a negative Jastrow parameter. `stabilization` is added to the diagonal of the
+/// Hamiltonian, except for the wavefunction itself, which shortens the steps, and it is raised
+/// tenfold until no parameter moves by more than `max_step`.
+#[derive(Clone, Debug)]
+pub struct LinearMethod {
+    pub stabilization: f64,
+    pub max_step: f64,
+}
+
+impl LinearMethod {
+    /// Returns the step of the linear method with `stabilization` added to the Hamiltonian
+    fn linear_step(hamiltonian: &DMatrix<f64>, overlap: &DMatrix<f64>, stabilization: f64) -> Result<DVector<f64>, String> {
+        let n = hamiltonian.nrows() - 1;
+        let mut hamiltonian = hamiltonian.clone();
+        for k in 1..=n {
+            hamiltonian[(k, k)] += stabilization;
+        }
+
+        // With the Cholesky factorization S = L L^T, the eigenvalues are those of L^-1 H L^-T
+        let inverse = overlap.clone()
+            .cholesky()
+            .ok_or_else(|| "The overlap matrix is singular.".to_owned())?
+            .l()
+            .try_inverse()
+            .ok_or_else(|| "The overlap matrix is singular.".to_owned())?;
+        let reduced = &inverse * &hamiltonian * inverse.transpose();
+        let lowest = reduced.complex_eigenvalues().iter().map(|eigenvalue| eigenvalue.re).fold(f64::INFINITY, f64::min);
+
+        // The eigenvector normalized to 1 along the wavefunction itself solves
+        // (H - lowest S) (1, dp) = 0, whose last n rows give dp
+        let shifted = &hamiltonian - overlap * lowest;
+        let force = -shifted.slice((1, 0), (n, 1)).into_owned();
+        let step = shifted.slice((1, 1), (n, n))
+            .into_owned()
+            .lu()
+            .solve(&force)
+            .ok_or_else(|| "The linear method eigenvalue problem is singular, try a larger stabilization.".to_owned())?;
+        Ok(step.column(0).into_owned())
+    }
+}
+
+impl Optimizer for LinearMethod {
+    fn step(&mut self, parameters: &[f64], vals: &SampledValues) -> Result<Vec<f64>, String> {
+        let energy = sampled(vals, "energy")?;
+        let (hamiltonian, overlap) = vals.derivatives.linear_method(energy)
+            .ok_or_else(|| "The linear method needs the derivatives of the local energy to be sampled.".to_owned())?;
+        // The eigenvalue solver never finishes on matrices with NaN in them
+        if hamiltonian.iter().chain(overlap.iter()).any(|x| !x.is_finite()) {
+            return Err(format!("The linear method matrices are not finite at the parameters {:?}.", parameters));
+        }
+
+        let mut stabilization = self.stabilization;
+        let mut step = Self::linear_step(&hamiltonian, &overlap, stabilization)?;
+        // Gives up after the stabilization is a million times the Hamiltonian
+        while step.amax() > self.max_step && stabilization < 1e6 * hamiltonian.amax() {
+            stabilization = (10. * stabilization).max(1e-6);
+            step = Self::linear_step(&hamiltonian, &overlap, stabilization)?;
+        }
+        Ok(parameters.iter().zip(step.iter()).map(|(parameter, step)| parameter + step).collect())
+    }
+
+    fn energy_derivatives(&self) -> bool {
+        true
+    }
+}
+
+/// The optimizers that can be chosen from the command line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Method {
+    GradientDescent,
+    StochasticReconfiguration,
+    Momentum,
+    Adam,
+    LineSearch,
+    Linear,
+}
+
+impl FromStr for Method {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gradient-descent" | "gd" => Ok(Method::GradientDescent),
+            "stochastic-reconfiguration" | "sr" => Ok(Method::StochasticReconfiguration),
+            "momentum" => Ok(Method::Momentum),
+            "adam" => Ok(Method::Adam),
+            "line-search" => Ok(Method::LineSearch),
+            "linear-method" | "linear" => Ok(Method::Linear),
+            _ => Err(format!(
+                "Unknown optimizer '{}', expected 'gradient-descent', 'stochastic-reconfiguration', 'momentum', 'adam', 'line-search' or 'linear-method'.",
+                s
+            )),
+        }
+    }
+}
+
+/// What an optimization minimizes. The variance of the local energy vanishes for any eigenstate,
+/// and is less sensitive to rare configurations than the energy is to the parameters it barely
+/// depends on, which makes it a more stable target for the Jastrow factor of weak traps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Objective {
+    Energy,
+    Variance,
+    /// (1 - w) E + w variance, for the given weight w of the variance
+    Mixed(f64),
+}
+
+impl Objective {
+    /// Weight of the variance in the cost
+    pub fn variance_weight(&self) -> f64 {
+        match *self {
+            Objective::Energy => 0.,
+            Objective::Variance => 1.,
+            Objective::Mixed(weight) => weight,
+        }
+    }
+}
+
+impl FromStr for Objective {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "energy" => Ok(Objective::Energy),
+            "variance" => Ok(Objective::Variance),
+            _ => match s.parse::<f64>() {
+                // The ends of the range are the pure objectives, and are optimized as such
+                Ok(0.) => Ok(Objective::Energy),
+                Ok(1.) => Ok(Objective::Variance),
+                Ok(weight) if (0. ..=1.).contains(&weight) => Ok(Objective::Mixed(weight)),
+                _ => Err(format!("Unknown objective '{}', expected 'energy', 'variance' or a weight of the variance between 0 and 1.", s)),
+            },
+        }
+    }
+}
+
+/// Parameters, energy and energy gradient of one iteration of an optimization.
+#[derive(Clone, Debug)]
+pub struct Iteration {
+    pub parameters: Vec<f64>,
+    pub energy: f64,
+    /// Standard error of the energy from the blocking method
+    pub error: f64,
+    pub variance: f64,
+    pub gradient: Vec<f64>,
+    /// Standard error of each derivative of the energy from the blocking method, NaN if there were
+    /// too few samples to estimate it
+    pub gradient_error: Vec<f64>,
+    pub particles: usize,
+    /// Time spent on the iteration, in seconds
+    pub time: f64,
+}
+
+/// Keeps every iteration of an optimization, and optionally streams them to a CSV file as they
+/// come in. Each row holds the iteration number, the parameters, the energy in total and per
+/// particle with its error and variance, the derivatives `d_<parameter>` of the minimized cost with
+/// their errors `d_<parameter>_error`, and the time spent.
+pub struct ConvergenceLog {
+    file: Option<BufWriter<File>>,
+    iterations: Vec<Iteration>,
+}
+
+impl ConvergenceLog {
+    /// Streams the log to a CSV file at `path`, as well as keeping it in memory.
+    pub fn file(path: &Path) -> Self {
+        ConvergenceLog { file: Some(BufWriter::new(create_file(path))), iterations: vec![] }
+    }
+
+    /// Only keeps the log in memory.
+    pub fn memory() -> Self {
+        ConvergenceLog { file: None, iterations: vec![] }
+    }
+
+    /// Returns the iterations logged so far
+    pub fn iterations(&self) -> &[Iteration] {
+        &self.iterations
+    }
+
+    /// Logs an iteration of an optimization of the parameters called `names`
+    pub fn record(&mut self, names: &[&str], iteration: Iteration) -> Result<(), String> {
+        if let Some(f) = &mut self.file {
+            if self.iterations.is_empty() {
+                let mut columns = vec!["iteration".to_owned()];
+                columns.extend(names.iter().map(|name| name.to_string()));
+                columns.extend(["energy[au]", "energy-per-particle[au]", "error", "variance"].iter().map(|s| s.to_string()));
+                columns.extend(names.iter().map(|name| format!("d_{}", name)));
+                columns.extend(names.iter().map(|name| format!("d_{}_error", name)));
+                columns.push("time[s]".to_owned());
+                writeln!(f, "{}", columns.join(",")).map_err(|why| why.to_string())?;
+            }
+
+            let mut row = vec![self.iterations.len() as f64];
+            row.extend(&iteration.parameters);
+            row.extend(&[iteration.energy, iteration.energy / iteration.particles as f64, iteration.error, iteration.variance]);
+            row.extend(&iteration.gradient);
+            row.extend(&iteration.gradient_error);
+            row.push(iteration.time);
+            let line: Vec<String> = row.iter().map(|val| val.to_string()).collect();
+            writeln!(f, "{}", line.join(",")).map_err(|why| why.to_string())?;
+            // Flushed right away, so that long optimizations can be followed as they run
+            f.flush().map_err(|why| why.to_string())?;
+        }
+        self.iterations.push(iteration);
+        Ok(())
+    }
+}
+
+/// When an optimization stops. It converges once every derivative of the energy is either smaller
+/// than `tolerance`, or within `significance` standard errors of zero, at which point the sampled
+/// gradient is mostly Monte Carlo noise and further steps would only follow the noise. A single
+/// noisy gradient is often within its errors far from the minimum too, so this has to hold on
+/// `patience` iterations in a row, with a step taken after each of them.
+#[derive(Clone, Debug)]
+pub struct Stopping {
+    pub tolerance: f64,
+    pub significance: f64,
+    pub patience: usize,
+    pub max_iterations: usize,
+}
+
+impl Stopping {
+    /// Returns whether `gradient`, with standard errors `errors` if they are known, has converged
+    pub fn converged(&self, gradient: &[f64], errors: Option<&[f64]>) -> bool {
+        gradient.iter().enumerate().all(|(k, derivative)| {
+            let noise = errors.map_or(0., |errors| self.significance * errors[k]);
+            derivative.abs() < self.tolerance.max(noise)
+        })
+    }
+}
+
+/// The outcome of `optimize`.
+#[derive(Clone, Debug)]
+pub struct Optimized<W> {
+    /// The wavefunction with the last parameters
+    pub wf: W,
+    pub iterations: usize,
+    /// Whether the gradient converged before running out of iterations
+    pub converged: bool,
+}
+
+/// Optimizes the variational parameters of `wf` with `optimizer`, logging every iteration to `log`.
+/// Each iteration samples `mc_cycles` cycles of `metro` of a system made by `new_system` from the
+/// wavefunction with the current parameters and the iteration number, which it can use to seed
+/// the system.
+pub fn optimize<T, D, W, F>(
+    mut wf: W,
+    mut new_system: F,
+    metro: &mut T,
+    mc_cycles: usize,
+    optimizer: &mut dyn Optimizer,
+    stopping: &Stopping,
+    log: &mut ConvergenceLog,
+) -> Result<Optimized<W>, String>
+where
+    T: Metropolis,
+    D: Dim,
+    W: TrialWaveFunction + Clone,
+    F: FnMut(W, usize) -> Result<System<D, W>, String>,
+    DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+{
+    let names = wf.parameter_names();
+    // Number of iterations in a row that looked converged
+    let mut streak = 0;
+    for i in 0..stopping.max_iterations {
+        let start = Instant::now();
+        let mut system = new_system(wf.clone(), i)?;
+        system.energy_derivatives |= optimizer.energy_derivatives();
+        let vals = montecarlo::monte_carlo(mc_cycles, &mut system, metro)?;
+
+        let energy = sampled(&vals, "energy")?;
+        let gradient = vals.derivatives.energy_gradient(energy);
+        let parameters = wf.parameters();
+        let gradient_error = vals.gradient_error();
+        streak = if stopping.converged(&gradient, gradient_error.as_deref()) { streak + 1 } else { 0 };
+        let converged = streak >= stopping.patience;
+        let next = if converged { None } else { Some(optimizer.step(&parameters, &vals)?) };
+
+        log.record(&names, Iteration {
+            parameters,
+            energy,
+            error: vals.error("energy").unwrap_or(0.),
+            variance: sampled(&vals, "energy_sqrd")? - energy.powi(2),
+            gradient_error: gradient_error.unwrap_or_else(|| vec![f64::NAN; gradient.len()]),
+            gradient,
+            particles: system.particles.len(),
+            time: start.elapsed().as_secs_f64(),
+        })?;
+
+        match next {
+            Some(next) => wf.set_parameters(&next)?,
+            None => return Ok(Optimized { wf, iterations: i + 1, converged: true }),
+        }
+    }
+    Ok(Optimized { wf, iterations: stopping.max_iterations, converged: false })
+}
+
+/// Settings of `minimize_correlated`.
+#[derive(Clone, Debug)]
+pub struct CorrelatedSampling {
+    pub objective: Objective,
+    /// Number of configurations sampled every iteration
+    pub walkers: usize,
+    /// Number of Metropolis steps between every sampled configuration
+    pub interval: usize,
+    /// Initial learning rate of the line search, which adapts it from there
+    pub learning_rate: f64,
+    /// Largest number of steps taken on the same configurations
+    pub max_updates: usize,
+}
+
+/// Minimizes `objective` of `CorrelatedSampling` with correlated sampling, logging every iteration
+/// to `log`. Each iteration samples configurations from a system made by `new_system`, like
+/// `optimize` does, and then takes gradient descent steps on the same configurations reweighted to
+/// the new parameters. The cost on fixed configurations is free of noise, so every step is checked
+/// to lower it, and otherwise retried with half the learning rate. The configurations are sampled
+/// anew after `max_updates` steps, or once the effective sample size has dropped below half of them.
+pub fn minimize_correlated<T, D, W, F>(
+    mut wf: W,
+    mut new_system: F,
+    metro: &mut T,
+    settings: &CorrelatedSampling,
+    stopping: &Stopping,
+    log: &mut ConvergenceLog,
+) -> Result<Optimized<W>, String>
+where
+    T: Metropolis,
+    D: Dim,
+    W: TrialWaveFunction + Clone,
+    F: FnMut(W, usize) -> Result<System<D, W>, String>,
+    DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+{
+    let names = wf.parameter_names();
+    let variance_weight = settings.objective.variance_weight();
+    if !(0. ..=1.).contains(&variance_weight) {
+        return Err(format!("The weight of the variance must be between 0 and 1, not {}.", variance_weight));
+    }
+    let mut learning_rate = settings.learning_rate;
+    let mut streak = 0;
+    for i in 0..stopping.max_iterations {
+        let start = Instant::now();
+        let mut system = new_system(wf.clone(), i)?;
+        let walkers = Walkers::sample(&mut system, metro, settings.walkers, settings.interval)?;
+        let mut current = walkers.reweight(&mut system, variance_weight)?;
+        streak = if stopping.converged(&current.gradient, current.gradient_error.as_deref()) { streak + 1 } else { 0 };
+        let converged = streak >= stopping.patience;
+
+        let mut parameters = wf.parameters();
+        log.record(&names, Iteration {
+            parameters: parameters.clone(),
+            energy: current.energy,
+            error: current.error,
+            variance: current.variance,
+            gradient: current.gradient.clone(),
+            gradient_error: current.gradient_error.clone().unwrap_or_else(|| vec![f64::NAN; parameters.len()]),
+            particles: system.particles.len(),
+            time: start.elapsed().as_secs_f64(),
+        })?;
+        if converged {
+            return Ok(Optimized { wf, iterations: i + 1, converged: true });
+        }
+
+        for _ in 0..settings.max_updates {
+            let trial: Vec<f64> = parameters.iter()
+                .zip(&current.gradient)
+                .map(|(parameter, derivative)| parameter - learning_rate * derivative)
+                .collect();
+            system.wf.set_parameters(&trial)?;
+            let reweighted = walkers.reweight(&mut system, variance_weight)?;
+            if reweighted.effective_sample_size < 0.5 * walkers.len() as f64 {
+                learning_rate *= 0.5;
+                break;
+            }
+            if reweighted.cost < current.cost {
+                parameters = trial;
+                current = reweighted;
+                learning_rate *= 1.2;
+            } else {
+                learning_rate *= 0.5;
+            }
+        }
+        wf.set_parameters(&parameters)?;
+    }
+    Ok(Optimized { wf, iterations: stopping.max_iterations, converged: false })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cli::SgdOptions, BruteForceMetropolis, GaussianProduct, ParameterDerivatives};
+    use structopt::StructOpt;
+    use nalgebra::Dynamic;
+
+    #[test]
+    fn test_reconfiguration_step() {
+        let mut vals = SampledValues::new();
+        vals.map.insert("energy".to_owned(), 2.);
+        vals.derivatives = ParameterDerivatives {
+            mean: vec![1., 0.],
+            times_energy: vec![3., 1.],
+            products: DMatrix::from_row_slice(2, 2, &[5., 1., 1., 2.]),
+            ..Default::default()
+        };
+        // The gradient is (2, 2), and S = [[4, 1], [1, 2]], so the step solves S x = -(1, 1)
+        let step = StochasticReconfiguration { time_step: 0.5, regularization: 0. }.step(&[0., 0.], &vals).unwrap();
+        assert!((step[0] + 0.5 / 7.).abs() < 1e-12);
+        assert!((step[1] + 0.5 * 3. / 7.).abs() < 1e-12);
+
+        let step = GradientDescent { learning_rate: 0.1 }.step(&[1., 1.], &vals).unwrap();
+        assert_eq!(step, vec![0.8, 0.8]);
+
+        // A parameter the wavefunction does not depend on makes S singular
+        vals.derivatives.products[(1, 1)] = 0.;
+        vals.derivatives.products[(0, 1)] = 0.;
+        vals.derivatives.products[(1, 0)] = 0.;
+        vals.derivatives.mean[1] = 0.;
+        assert!(StochasticReconfiguration { time_step: 0.5, regularization: 0.1 }.step(&[0., 0.], &vals).is_err());
+    }
+
+    #[test]
+    fn test_adaptive_steps() {
+        let mut vals = SampledValues::new();
+        vals.map.insert("energy".to_owned(), 2.);
+        vals.derivatives = ParameterDerivatives {
+            mean: vec![1., 0.],
+            times_energy: vec![3., 0.05],
+            products: DMatrix::from_row_slice(2, 2, &[5., 1., 1., 2.]),
+            ..Default::default()
+        };
+        // The gradient is (2, 0.1), but Adam moves both parameters by the learning rate
+        let step = Adam::new(0.1).step(&[0., 0.], &vals).unwrap();
+        assert!((step[0] + 0.1).abs() < 1e-6 && (step[1] + 0.1).abs() < 1e-6);
+
+        let mut momentum = Momentum::new(0.1, 0.5);
+        assert!((momentum.step(&[0., 0.], &vals).unwrap()[0] + 0.2).abs() < 1e-12);
+        assert!((momentum.step(&[0., 0.], &vals).unwrap()[0] + 0.3).abs() < 1e-12);
+
+        // The line search grows the step while the energy goes down, and backtracks when it goes up
+        let mut line_search = LineSearch::new(0.1);
+        assert!((line_search.step(&[1., 1.], &vals).unwrap()[0] - 0.8).abs() < 1e-12);
+        // With a mean energy of 1 the gradient is (4, 0.1)
+        vals.map.insert("energy".to_owned(), 1.);
+        assert!((line_search.step(&[0.8, 1.], &vals).unwrap()[0] - (0.8 - 0.12 * 4.)).abs() < 1e-12);
+        vals.map.insert("energy".to_owned(), 3.);
+        assert!((line_search.step(&[0.32, 1.], &vals).unwrap()[0] - (0.8 - 0.06 * 4.)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_stopping() {
+        let stopping = Stopping { tolerance: 1e-3, significance: 2., patience: 1, max_iterations: 1 };
+        assert!(stopping.converged(&[1e-4, -1e-4], None));
+        assert!(!stopping.converged(&[0.1, -1e-4], None));
+        assert!(stopping.converged(&[0.1, -1e-4], Some(&[0.06, 0.])));
+        assert!(!stopping.converged(&[0.1, -1e-4], Some(&[0.04, 0.])));
+    }
+
+    /// Makes the system of iteration `i` for four non-interacting bosons in two dimensions, whose
+    /// exact ground state is a `GaussianProduct` with alpha = 1
+    fn bosons(wf: GaussianProduct, i: usize) -> Result<System<Dynamic, GaussianProduct>, String> {
+        System::new(4, 2, wf, false, false, 1., i as u64)
+    }
+
+    /// The trial wavefunction of `bosons` with the given alpha
+    fn gaussians(alpha: f64) -> GaussianProduct {
+        GaussianProduct { alpha, omega: 1., anisotropy: [1.; 3] }
+    }
+
+    #[test]
+    fn test_default_stopping() {
+        // With few samples the first gradients are within their errors well away from the minimum,
+        // which stops after the first few steps unless it has to hold for several iterations
+        let stopping = SgdOptions::from_iter(&[""]).stopping();
+        let mut optimizer = GradientDescent { learning_rate: 0.05 };
+        let mut log = ConvergenceLog::memory();
+        let optimized = optimize(gaussians(0.6), bosons, &mut BruteForceMetropolis::new(1.), 50, &mut optimizer, &Stopping { patience: 1, ..stopping.clone() }, &mut log).unwrap();
+        assert!((optimized.wf.alpha - 0.6).abs() < 0.1);
+        let optimized = optimize(gaussians(0.6), bosons, &mut BruteForceMetropolis::new(1.), 50, &mut optimizer, &stopping, &mut log).unwrap();
+        assert!((optimized.wf.alpha - 1.).abs() < 0.05);
+
+        // The streak starts over when a gradient is clearly nonzero
+        let stopping = Stopping { tolerance: 0.5, significance: 0., patience: 2, max_iterations: 10 };
+        let mut log = ConvergenceLog::memory();
+        let optimized = optimize(gaussians(0.5), bosons, &mut BruteForceMetropolis::new(1.), 2000, &mut optimizer, &stopping, &mut log).unwrap();
+        let last = log.iterations().len() - 1;
+        assert!(optimized.converged);
+        assert!(log.iterations()[last - 1..].iter().all(|iteration| iteration.gradient[0].abs() < 0.5));
+        assert!(log.iterations()[..last - 1].iter().any(|iteration| iteration.gradient[0].abs() >= 0.5));
+    }
+
+    #[test]
+    fn test_linear_method() {
+        let wf = gaussians(0.5);
+        let stopping = Stopping { tolerance: 1e-3, significance: 0., patience: 1, max_iterations: 10 };
+        let mut log = ConvergenceLog::memory();
+        let optimized = optimize(wf, bosons, &mut BruteForceMetropolis::new(1.), 2000, &mut LinearMethod { stabilization: 0., max_step: 1. }, &stopping, &mut log).unwrap();
+
+        // The energy is quadratic enough around the minimum for a few Newton-like steps to find it
+        assert!(optimized.converged);
+        assert!(optimized.iterations <= 5);
+
+        // Raising the stabilization keeps every step within a smaller `max_step`
+        let wf = gaussians(0.5);
+        let mut log = ConvergenceLog::memory();
+        optimize(wf, bosons, &mut BruteForceMetropolis::new(1.), 2000, &mut LinearMethod { stabilization: 0., max_step: 0.1 }, &stopping, &mut log).unwrap();
+        assert!(log.iterations().windows(2).all(|pair| (pair[1].parameters[0] - pair[0].parameters[0]).abs() <= 0.1 + 1e-12));
+    }
+
+    #[test]
+    fn test_minimize_variance() {
+        let wf = gaussians(0.6);
+        let stopping = Stopping { tolerance: 1e-3, significance: 0., patience: 1, max_iterations: 20 };
+        let settings = CorrelatedSampling {
+            objective: "variance".parse().unwrap(),
+            walkers: 1000,
+            interval: 4,
+            learning_rate: 0.1,
+            max_updates: 10,
+        };
+        let mut log = ConvergenceLog::memory();
+        let optimized = minimize_correlated(wf, bosons, &mut BruteForceMetropolis::new(1.), &settings, &stopping, &mut log).unwrap();
+
+        // The variance vanishes for the exact ground state only, whose local energy is 4 everywhere
+        assert!(optimized.converged);
+        let (first, last) = (&log.iterations()[0], log.iterations().last().unwrap());
+        assert!(first.variance > 0.5 && last.variance < 1e-3);
+        assert!((last.energy - 4.).abs() < 1e-3);
+
+        assert_eq!("0.3".parse::<Objective>(), Ok(Objective::Mixed(0.3)));
+        assert_eq!("0".parse::<Objective>(), Ok(Objective::Energy));
+        assert_eq!("1".parse::<Objective>(), Ok(Objective::Variance));
+        assert!("2".parse::<Objective>().is_err());
+        assert!("-0.5".parse::<Objective>().is_err());
+
+        let settings = CorrelatedSampling { objective: Objective::Mixed(1.5), ..settings };
+        assert!(minimize_correlated(optimized.wf, bosons, &mut BruteForceMetropolis::new(1.), &settings, &stopping, &mut log).is_err());
+    }
+
+    #[test]
+    fn test_optimize() {
+        let wf = gaussians(0.5);
+        let stopping = Stopping { tolerance: 1e-3, significance: 0., patience: 1, max_iterations: 50 };
+        let mut optimizer = StochasticReconfiguration { time_step: 0.5, regularization: 1e-3 };
+        let mut log = ConvergenceLog::memory();
+        let optimized = optimize(wf, bosons, &mut BruteForceMetropolis::new(1.), 5000, &mut optimizer, &stopping, &mut log).unwrap();
+
+        // For a Gaussian of width alpha the overlap is N / (4 alpha^2) and the energy derivative
+        // N d (1 - 1 / alpha^2) / 4, so the first step is -time_step (alpha^2 - 1) whatever N is
+        let first_step = log.iterations()[1].parameters[0] - 0.5;
+        assert!((first_step - 0.5 * 0.75).abs() < 1e-2);
+        assert!(optimized.converged);
+        assert_eq!(log.iterations().len(), optimized.iterations);
+    }
+}
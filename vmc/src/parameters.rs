@@ -0,0 +1,597 @@
+use crate::{
+    basis::filled_shells,
+    config,
+    interaction::{Contact, Coulomb, Interaction, SoftenedCoulomb, Yukawa},
+    potential::{CoulombCenter, DoubleWell, ExternalPotential, FiniteWell, HarmonicOscillator},
+    system::Refresh,
+    utils::find_cargo_root,
+    Hamiltonian, Particle,
+};
+
+use serde::{Deserialize, Serialize};
+use std::{convert::TryFrom, path::PathBuf, str::FromStr};
+use structopt::StructOpt;
+
+/// Particle counts the static backend is compiled for. Its `System` is sized by the number of
+/// particles, so every supported value has to be monomorphized ahead of time (see
+/// `run::dispatch`). These are the closed-shell systems in two and three dimensions.
+pub const SUPPORTED_N: [usize; 9] = [2, 6, 8, 12, 20, 30, 40, 42, 56];
+
+/// The Metropolis algorithm used to sample the system.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sampler {
+    BruteForce,
+    Importance,
+}
+
+impl FromStr for Sampler {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "brute-force" | "bruteforce" => Ok(Sampler::BruteForce),
+            "importance" => Ok(Sampler::Importance),
+            _ => Err(format!("Unknown sampler '{}', expected 'brute-force' or 'importance'.", s)),
+        }
+    }
+}
+
+/// How the Slater matrices of a system are sized.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// Sized at compile time, which is faster but only supports the particle counts in
+    /// `SUPPORTED_N`
+    Static,
+    /// Sized at runtime, for any closed-shell system
+    Dynamic,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "static" => Ok(Backend::Static),
+            "dynamic" => Ok(Backend::Dynamic),
+            _ => Err(format!("Unknown backend '{}', expected 'static' or 'dynamic'.", s)),
+        }
+    }
+}
+
+/// Evenly spaced values from `start` to `end`, both included. Written as `start:end:points`, or as
+/// a single value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Range {
+    pub start: f64,
+    pub end: f64,
+    pub points: usize,
+}
+
+impl Range {
+    pub fn values(&self) -> Vec<f64> {
+        if self.points == 1 {
+            return vec![self.start];
+        }
+        let step = (self.end - self.start) / (self.points - 1) as f64;
+        (0..self.points).map(|i| self.start + step * i as f64).collect()
+    }
+}
+
+impl FromStr for Range {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid range '{}', expected 'start:end:points' or a single value.", s);
+        let parts: Vec<&str> = s.split(':').collect();
+        match parts[..] {
+            [value] => {
+                let value = value.parse().map_err(|_| invalid())?;
+                Ok(Range { start: value, end: value, points: 1 })
+            }
+            [start, end, points] => {
+                let range = Range {
+                    start: start.parse().map_err(|_| invalid())?,
+                    end: end.parse().map_err(|_| invalid())?,
+                    points: points.parse().map_err(|_| invalid())?,
+                };
+                if range.points == 0 {
+                    return Err(invalid());
+                }
+                Ok(range)
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// What to do when the inverse Slater matrix has drifted too far from one computed from scratch,
+/// or the Slater matrix is close to singular.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DriftPolicy {
+    /// Print a warning and carry on with the recomputed inverse
+    Warn,
+    /// Stop the simulation with an error
+    Fail,
+}
+
+impl FromStr for DriftPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(DriftPolicy::Warn),
+            "fail" => Ok(DriftPolicy::Fail),
+            _ => Err(format!("Unknown drift policy '{}', expected 'warn' or 'fail'.", s)),
+        }
+    }
+}
+
+/// Splits `name:value:value..` into the name and the values.
+fn named_values(s: &str) -> Option<(&str, Vec<f64>)> {
+    let mut parts = s.split(':');
+    let name = parts.next()?;
+    let values = parts.map(|value| value.parse().ok()).collect::<Option<Vec<f64>>>()?;
+    Some((name, values))
+}
+
+/// The external potential every particle feels. Written as `harmonic`, `double-well:<separation>`,
+/// `coulomb-center:<charge>` or `finite-well:<depth>:<radius>`. The harmonic oscillator and the
+/// double well take their frequency from omega.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Potential {
+    Harmonic,
+    DoubleWell { separation: f64 },
+    CoulombCenter { charge: f64 },
+    FiniteWell { depth: f64, radius: f64 },
+}
+
+impl FromStr for Potential {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match named_values(s).as_ref().map(|(name, values)| (*name, &values[..])) {
+            Some(("harmonic", [])) => Ok(Potential::Harmonic),
+            Some(("double-well", &[separation])) if separation >= 0. => Ok(Potential::DoubleWell { separation }),
+            Some(("coulomb-center", &[charge])) if charge > 0. => Ok(Potential::CoulombCenter { charge }),
+            Some(("finite-well", &[depth, radius])) if radius > 0. => Ok(Potential::FiniteWell { depth, radius }),
+            _ => Err(format!(
+                "Unknown potential '{}', expected 'harmonic', 'double-well:<separation>', 'coulomb-center:<charge>' or 'finite-well:<depth>:<radius>'.",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Potential {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Potential> for String {
+    fn from(potential: Potential) -> Self {
+        match potential {
+            Potential::Harmonic => "harmonic".to_owned(),
+            Potential::DoubleWell { separation } => format!("double-well:{}", separation),
+            Potential::CoulombCenter { charge } => format!("coulomb-center:{}", charge),
+            Potential::FiniteWell { depth, radius } => format!("finite-well:{}:{}", depth, radius),
+        }
+    }
+}
+
+/// The interaction between every pair of particles of interacting systems. Written as `coulomb`,
+/// `softened:<epsilon>`, `yukawa:<screening>` or `contact:<strength>:<width>`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum PairInteraction {
+    Coulomb,
+    Softened { epsilon: f64 },
+    Yukawa { screening: f64 },
+    Contact { strength: f64, width: f64 },
+}
+
+impl FromStr for PairInteraction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match named_values(s).as_ref().map(|(name, values)| (*name, &values[..])) {
+            Some(("coulomb", [])) => Ok(PairInteraction::Coulomb),
+            Some(("softened", &[epsilon])) if epsilon > 0. => Ok(PairInteraction::Softened { epsilon }),
+            Some(("yukawa", &[screening])) if screening >= 0. => Ok(PairInteraction::Yukawa { screening }),
+            Some(("contact", &[strength, width])) if width > 0. => Ok(PairInteraction::Contact { strength, width }),
+            _ => Err(format!(
+                "Unknown interaction '{}', expected 'coulomb', 'softened:<epsilon>', 'yukawa:<screening>' or 'contact:<strength>:<width>'.",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for PairInteraction {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PairInteraction> for String {
+    fn from(interaction: PairInteraction) -> Self {
+        match interaction {
+            PairInteraction::Coulomb => "coulomb".to_owned(),
+            PairInteraction::Softened { epsilon } => format!("softened:{}", epsilon),
+            PairInteraction::Yukawa { screening } => format!("yukawa:{}", screening),
+            PairInteraction::Contact { strength, width } => format!("contact:{}:{}", strength, width),
+        }
+    }
+}
+
+/// The full set of parameters describing a simulation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Parameters {
+    pub alpha: f64,
+    pub beta: f64,
+    pub omega: f64,
+    /// Trap frequency along each axis, relative to omega
+    pub anisotropy: [f64; 3],
+    pub jastrow: bool,
+    pub interacting: bool,
+    pub numerical_laplace: bool,
+    pub sampler: Sampler,
+    pub step_size: f64,
+    pub mc_cycles: usize,
+    pub dim: usize,
+    pub n: usize,
+    pub backend: Backend,
+    pub spread: f64,
+    /// Number of accepted moves between every recomputation of the inverse Slater matrix. 0 never
+    /// recomputes it
+    pub refresh_interval: usize,
+    /// Largest drift of the inverse Slater matrix, relative to its largest element, that is allowed
+    pub drift_tolerance: f64,
+    pub drift_policy: DriftPolicy,
+    pub potential: Potential,
+    pub interaction: PairInteraction,
+    /// Master seed of the random number generators
+    pub seed: u64,
+    /// Directory the data files are written to
+    pub output: PathBuf,
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        let mut output = find_cargo_root().unwrap_or_default();
+        output.push("data");
+
+        Parameters {
+            alpha: 1.0,
+            beta: 1.0,
+            omega: 1.0,
+            anisotropy: [1.0; 3],
+            jastrow: true,
+            interacting: true,
+            numerical_laplace: false,
+            sampler: Sampler::BruteForce,
+            step_size: 0.1,
+            mc_cycles: 100_000,
+            dim: 2,
+            n: 2,
+            backend: Backend::Static,
+            spread: 0.1,
+            refresh_interval: 1000,
+            drift_tolerance: 1e-6,
+            drift_policy: DriftPolicy::Warn,
+            potential: Potential::Harmonic,
+            interaction: PairInteraction::Coulomb,
+            // TOML integers are signed, so keep the seed within 63 bits to be able to write it
+            seed: rand::random::<u64>() >> 1,
+            output,
+        }
+    }
+}
+
+// Parameters that override the defaults of a run. Anything left as `None` keeps the default.
+// NOTE: Not a doc comment, as structopt would use it as the about text of every subcommand.
+#[derive(StructOpt, Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Overrides {
+    /// TOML or JSON file to read parameters from. Flags take precedence over the file
+    #[structopt(short, long)]
+    #[serde(skip)]
+    pub config: Option<PathBuf>,
+    /// Variational parameter of the single particle functions
+    #[structopt(long)]
+    pub alpha: Option<f64>,
+    /// Variational parameter of the Jastrow factor
+    #[structopt(long)]
+    pub beta: Option<f64>,
+    /// Frequency of the harmonic oscillator trap
+    #[structopt(long)]
+    pub omega: Option<f64>,
+    /// Trap frequency along each axis relative to omega, e.g. 1,1,2.8. Missing axes are 1
+    #[structopt(long, use_delimiter = true)]
+    pub anisotropy: Option<Vec<f64>>,
+    /// Trap frequency along the z-axis relative to omega. Takes precedence over anisotropy
+    #[structopt(long)]
+    pub lambda: Option<f64>,
+    /// Whether to include the Jastrow factor [true|false]
+    #[structopt(long)]
+    pub jastrow: Option<bool>,
+    /// Whether the particles interact, see --interaction [true|false]
+    #[structopt(long)]
+    pub interacting: Option<bool>,
+    /// Whether to compute the Laplacian numerically [true|false]
+    #[structopt(long)]
+    pub numerical_laplace: Option<bool>,
+    /// Metropolis algorithm [brute-force|importance]
+    #[structopt(long)]
+    pub sampler: Option<Sampler>,
+    /// Step size of the brute force Metropolis algorithm
+    #[structopt(long)]
+    pub step_size: Option<f64>,
+    /// Number of Monte Carlo cycles
+    #[structopt(long)]
+    pub mc_cycles: Option<usize>,
+    /// Number of spatial dimensions
+    #[structopt(long)]
+    pub dim: Option<usize>,
+    /// Number of particles
+    #[structopt(short, long)]
+    pub n: Option<usize>,
+    /// How the Slater matrices are sized. The dynamic backend supports any closed-shell number of
+    /// particles [static|dynamic]
+    #[structopt(long)]
+    pub backend: Option<Backend>,
+    /// Spread of the initial particle positions
+    #[structopt(long)]
+    pub spread: Option<f64>,
+    /// Number of accepted moves between every recomputation of the inverse Slater matrix, 0 for never
+    #[structopt(long)]
+    pub refresh_interval: Option<usize>,
+    /// Largest relative drift of the inverse Slater matrix before the drift policy kicks in
+    #[structopt(long)]
+    pub drift_tolerance: Option<f64>,
+    /// What to do about too much drift or a nearly singular Slater matrix [warn|fail]
+    #[structopt(long)]
+    pub drift_policy: Option<DriftPolicy>,
+    /// External potential, with the same frequency as the trial wavefunction
+    /// [harmonic|double-well:<separation>|coulomb-center:<charge>|finite-well:<depth>:<radius>]
+    #[structopt(long)]
+    pub potential: Option<Potential>,
+    /// Interaction between the particles when --interacting is set
+    /// [coulomb|softened:<epsilon>|yukawa:<screening>|contact:<strength>:<width>]
+    #[structopt(long)]
+    pub interaction: Option<PairInteraction>,
+    /// Master seed of the random number generators, at most 2^63 - 1 [default: random]
+    #[structopt(long)]
+    pub seed: Option<u64>,
+    /// Directory to write the data files to [default: data/ in the crate root]
+    #[structopt(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+impl Overrides {
+    /// Applies the overrides on top of `defaults`, and checks that the result is something we
+    /// are able to simulate. If a config file is given, it is applied first.
+    pub fn apply(&self, defaults: Parameters) -> Result<Parameters, String> {
+        let defaults = match &self.config {
+            Some(path) => config::load(path)?.apply(defaults)?,
+            None => defaults,
+        };
+
+        let mut anisotropy = match &self.anisotropy {
+            Some(ratios) if ratios.is_empty() || ratios.len() > 3 => {
+                return Err(format!("Expected 1 to 3 trap frequency ratios, got {}.", ratios.len()));
+            }
+            Some(ratios) => {
+                let mut anisotropy = [1.0; 3];
+                anisotropy[..ratios.len()].copy_from_slice(ratios);
+                anisotropy
+            }
+            None => defaults.anisotropy,
+        };
+        if let Some(lambda) = self.lambda {
+            anisotropy[2] = lambda;
+        }
+
+        let params = Parameters {
+            alpha: self.alpha.unwrap_or(defaults.alpha),
+            beta: self.beta.unwrap_or(defaults.beta),
+            omega: self.omega.unwrap_or(defaults.omega),
+            anisotropy,
+            jastrow: self.jastrow.unwrap_or(defaults.jastrow),
+            interacting: self.interacting.unwrap_or(defaults.interacting),
+            numerical_laplace: self.numerical_laplace.unwrap_or(defaults.numerical_laplace),
+            sampler: self.sampler.unwrap_or(defaults.sampler),
+            step_size: self.step_size.unwrap_or(defaults.step_size),
+            mc_cycles: self.mc_cycles.unwrap_or(defaults.mc_cycles),
+            dim: self.dim.unwrap_or(defaults.dim),
+            n: self.n.unwrap_or(defaults.n),
+            backend: self.backend.unwrap_or(defaults.backend),
+            spread: self.spread.unwrap_or(defaults.spread),
+            refresh_interval: self.refresh_interval.unwrap_or(defaults.refresh_interval),
+            drift_tolerance: self.drift_tolerance.unwrap_or(defaults.drift_tolerance),
+            drift_policy: self.drift_policy.unwrap_or(defaults.drift_policy),
+            potential: self.potential.unwrap_or(defaults.potential),
+            interaction: self.interaction.unwrap_or(defaults.interaction),
+            seed: self.seed.unwrap_or(defaults.seed),
+            output: self.output.clone().unwrap_or(defaults.output),
+        };
+        params.validate()?;
+        Ok(params)
+    }
+}
+
+impl Parameters {
+    /// Checks that the parameters describe a system that can be simulated
+    pub fn validate(&self) -> Result<(), String> {
+        if self.backend == Backend::Static && !SUPPORTED_N.contains(&self.n) {
+            return Err(format!(
+                "Unsupported number of particles {}, expected one of {:?}, or the dynamic backend.",
+                self.n, SUPPORTED_N
+            ));
+        }
+        if !(1..=3).contains(&self.dim) {
+            return Err(format!("Unsupported dimensionality {}, expected 1, 2 or 3.", self.dim));
+        }
+        if self.anisotropy.iter().any(|&ratio| ratio <= 0.) {
+            return Err(format!("Trap frequency ratios must be positive, got {:?}.", self.anisotropy));
+        }
+        if self.drift_tolerance <= 0. {
+            return Err(format!("Drift tolerance must be positive, got {}.", self.drift_tolerance));
+        }
+        if self.omega <= 0. {
+            return Err(format!("The trap frequency omega must be positive, got {}.", self.omega));
+        }
+        if self.alpha <= 0. {
+            return Err(format!("alpha must be positive for the wavefunction to be normalizable, got {}.", self.alpha));
+        }
+        // The Jastrow factor blows up at a distance of -1 / beta between two particles
+        if self.beta < 0. {
+            return Err(format!("beta must not be negative, got {}.", self.beta));
+        }
+        // TOML integers are signed, so a larger seed could not be read back once written
+        if self.seed > i64::MAX as u64 {
+            return Err(format!("The seed must be at most {}, got {}.", i64::MAX, self.seed));
+        }
+        filled_shells(self.n, self.dim)?;
+        Ok(())
+    }
+
+    /// Returns the Hamiltonian with the chosen potential and interaction
+    pub fn hamiltonian(&self) -> Hamiltonian {
+        let external: Box<dyn ExternalPotential> = match self.potential {
+            Potential::Harmonic => Box::new(HarmonicOscillator { omega: self.omega, anisotropy: self.anisotropy }),
+            Potential::DoubleWell { separation } => Box::new(DoubleWell { omega: self.omega, separation }),
+            Potential::CoulombCenter { charge } => Box::new(CoulombCenter { charge }),
+            Potential::FiniteWell { depth, radius } => Box::new(FiniteWell { depth, radius }),
+        };
+        let interaction: Box<dyn Interaction> = match self.interaction {
+            PairInteraction::Coulomb => Box::new(Coulomb),
+            PairInteraction::Softened { epsilon } => Box::new(SoftenedCoulomb { epsilon }),
+            PairInteraction::Yukawa { screening } => Box::new(Yukawa { screening }),
+            PairInteraction::Contact { strength, width } => Box::new(Contact { strength, width }),
+        };
+        Hamiltonian::new(
+            move |particle: &Particle| external.evaluate(particle),
+            move |r: f64, dim: usize| interaction.evaluate(r, dim),
+        )
+    }
+
+    /// Returns the settings of the recomputation of the inverse Slater matrix
+    pub fn refresh(&self) -> Refresh {
+        Refresh { interval: self.refresh_interval, tolerance: self.drift_tolerance, policy: self.drift_policy }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let overrides = Overrides::from_iter(&["", "--alpha", "0.5", "--jastrow", "true", "--sampler", "importance"]);
+        let params = overrides.apply(Parameters { jastrow: false, ..Default::default() }).unwrap();
+        assert_eq!(params.alpha, 0.5);
+        assert_eq!(params.beta, 1.0);
+        assert!(params.jastrow);
+        assert!(matches!(params.sampler, Sampler::Importance));
+    }
+
+    #[test]
+    fn test_apply_rejects_unsupported_n() {
+        let overrides = Overrides { n: Some(3), ..Default::default() };
+        assert!(overrides.apply(Parameters { jastrow: false, ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn test_apply_dynamic_backend() {
+        let overrides = Overrides::from_iter(&["", "-n", "72", "--dim", "2", "--backend", "dynamic"]);
+        assert_eq!(overrides.apply(Parameters::default()).unwrap().n, 72);
+        let overrides = Overrides { n: Some(72), ..Default::default() };
+        assert!(overrides.apply(Parameters::default()).is_err());
+        let overrides = Overrides { n: Some(10), backend: Some(Backend::Dynamic), ..Default::default() };
+        assert!(overrides.apply(Parameters::default()).is_err());
+    }
+
+    #[test]
+    fn test_apply_anisotropy() {
+        let overrides = Overrides::from_iter(&["", "--anisotropy", "1,2"]);
+        assert_eq!(overrides.apply(Parameters::default()).unwrap().anisotropy, [1., 2., 1.]);
+
+        let overrides = Overrides::from_iter(&["", "--anisotropy", "1,2,3", "--lambda", "2.8"]);
+        assert_eq!(overrides.apply(Parameters::default()).unwrap().anisotropy, [1., 2., 2.8]);
+
+        let overrides = Overrides { anisotropy: Some(vec![1., 1., 1., 1.]), ..Default::default() };
+        assert!(overrides.apply(Parameters::default()).is_err());
+        let overrides = Overrides { lambda: Some(0.), ..Default::default() };
+        assert!(overrides.apply(Parameters::default()).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Parameters::default().validate().is_ok());
+        assert!(Parameters { omega: 0., ..Default::default() }.validate().is_err());
+        assert!(Parameters { alpha: -0.5, ..Default::default() }.validate().is_err());
+        assert!(Parameters { beta: -0.1, ..Default::default() }.validate().is_err());
+        assert!(Parameters { beta: 0., ..Default::default() }.validate().is_ok());
+    }
+
+    #[test]
+    fn test_potential() {
+        assert_eq!("harmonic".parse::<Potential>(), Ok(Potential::Harmonic));
+        assert_eq!("double-well:2".parse::<Potential>(), Ok(Potential::DoubleWell { separation: 2. }));
+        assert_eq!("finite-well:5:3.5".parse::<Potential>(), Ok(Potential::FiniteWell { depth: 5., radius: 3.5 }));
+        assert!("coulomb-center:-1".parse::<Potential>().is_err());
+        assert!("finite-well:5".parse::<Potential>().is_err());
+        assert!("double-well".parse::<Potential>().is_err());
+
+        let potential = Potential::CoulombCenter { charge: 2. };
+        assert_eq!(String::from(potential).parse::<Potential>(), Ok(potential));
+
+        // The chosen potential is the one the local energy is computed from
+        let params = Parameters { potential, interacting: false, ..Default::default() };
+        let wf = crate::WaveFunction { alpha: 1., beta: 0., omega: 1., anisotropy: [1.; 3], jastrow_on: false };
+        let mut sys = crate::System::<nalgebra::Const<2>>::new(2, 2, wf, false, false, 1., 1).unwrap();
+        sys.hamiltonian = params.hamiltonian();
+        let (energy, kinetic, _) = sys.hamiltonian.energy(&sys).unwrap();
+        let want: f64 = sys.particles.iter().map(|particle| -2. / particle.squared_sum().sqrt()).sum();
+        assert!((energy - kinetic - want).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_pair_interaction() {
+        assert_eq!("coulomb".parse::<PairInteraction>(), Ok(PairInteraction::Coulomb));
+        assert_eq!("yukawa:0.5".parse::<PairInteraction>(), Ok(PairInteraction::Yukawa { screening: 0.5 }));
+        assert_eq!("contact:-1:0.2".parse::<PairInteraction>(), Ok(PairInteraction::Contact { strength: -1., width: 0.2 }));
+        assert!("softened:0".parse::<PairInteraction>().is_err());
+        assert!("contact:1".parse::<PairInteraction>().is_err());
+        assert!("yukawa:x".parse::<PairInteraction>().is_err());
+
+        let interaction = PairInteraction::Softened { epsilon: 0.5 };
+        assert_eq!(String::from(interaction).parse::<PairInteraction>(), Ok(interaction));
+
+        // The chosen interaction is the one the local energy is computed from
+        let params = Parameters { interaction, ..Default::default() };
+        let wf = crate::WaveFunction { alpha: 1., beta: 0., omega: 1., anisotropy: [1.; 3], jastrow_on: false };
+        let mut sys = crate::System::<nalgebra::Const<2>>::new(2, 2, wf, true, false, 1., 1).unwrap();
+        sys.hamiltonian = params.hamiltonian();
+        let r = sys.particles[0].distance_to(&sys.particles[1]).unwrap();
+        let (_, _, interaction_energy) = sys.hamiltonian.energy(&sys).unwrap();
+        assert!((interaction_energy - 1. / (r * r + 0.25).sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!("0.5:1:3".parse::<Range>().unwrap().values(), vec![0.5, 0.75, 1.]);
+        assert_eq!("0.3".parse::<Range>().unwrap().values(), vec![0.3]);
+        assert!("0.5:1".parse::<Range>().is_err());
+        assert!("0.5:1:0".parse::<Range>().is_err());
+    }
+}
@@ -50,9 +50,15 @@ impl Particle {
 
     /// Computes the squared sum of each coordinate, but the z-component is scaled by a factor
     pub fn squared_sum_scaled_z(&self, factor: f64) -> f64 {
+        self.squared_sum_scaled(&[1., 1., factor])
+    }
+
+    /// Computes the squared sum of each coordinate, each scaled by the factor of its axis
+    pub fn squared_sum_scaled(&self, factors: &[f64; 3]) -> f64 {
         match self.position {
-            D1(_) | D2(_, _) => self.squared_sum(),
-            D3(x, y, z) => x.powi(2) + y.powi(2) + factor * z.powi(2),
+            D1(x) => factors[0] * x.powi(2),
+            D2(x, y) => factors[0] * x.powi(2) + factors[1] * y.powi(2),
+            D3(x, y, z) => factors[0] * x.powi(2) + factors[1] * y.powi(2) + factors[2] * z.powi(2),
         }
     }
 
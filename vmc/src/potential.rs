@@ -0,0 +1,102 @@
+use crate::Particle;
+
+/// External potential felt by every particle on its own. Anything that maps a particle to an
+/// energy, including closures, can be used as one.
+pub trait ExternalPotential: Send + Sync {
+    /// Returns the potential energy of a single particle
+    fn evaluate(&self, particle: &Particle) -> f64;
+}
+
+impl<F: Fn(&Particle) -> f64 + Send + Sync> ExternalPotential for F {
+    fn evaluate(&self, particle: &Particle) -> f64 {
+        self(particle)
+    }
+}
+
+/// Harmonic oscillator trap, 0.5 omega^2 r^2, with its own frequency along each axis.
+#[derive(Clone, Debug)]
+pub struct HarmonicOscillator {
+    pub omega: f64,
+    /// Trap frequency along each axis, relative to `omega`
+    pub anisotropy: [f64; 3],
+}
+
+impl ExternalPotential for HarmonicOscillator {
+    fn evaluate(&self, particle: &Particle) -> f64 {
+        let squared_anisotropy = self.anisotropy.map(|ratio| ratio * ratio);
+        0.5 * self.omega.powi(2) * particle.squared_sum_scaled(&squared_anisotropy)
+    }
+}
+
+/// Two harmonic oscillator wells of frequency `omega` centered at x = -separation / 2 and
+/// x = separation / 2, joined where they meet at x = 0.
+#[derive(Clone, Debug)]
+pub struct DoubleWell {
+    pub omega: f64,
+    pub separation: f64,
+}
+
+impl ExternalPotential for DoubleWell {
+    fn evaluate(&self, particle: &Particle) -> f64 {
+        let x = particle.position.get(0).unwrap();
+        let shifted = particle.squared_sum() - x.powi(2) + (x.abs() - 0.5 * self.separation).powi(2);
+        0.5 * self.omega.powi(2) * shifted
+    }
+}
+
+/// Attraction to a point charge `charge` fixed at the origin, -Z / r, like the nucleus of an atom.
+#[derive(Clone, Debug)]
+pub struct CoulombCenter {
+    pub charge: f64,
+}
+
+impl ExternalPotential for CoulombCenter {
+    fn evaluate(&self, particle: &Particle) -> f64 {
+        -self.charge / particle.squared_sum().sqrt()
+    }
+}
+
+/// Spherical (circular in two dimensions) well of depth `depth` and radius `radius`, which is 0
+/// outside the well.
+#[derive(Clone, Debug)]
+pub struct FiniteWell {
+    pub depth: f64,
+    pub radius: f64,
+}
+
+impl ExternalPotential for FiniteWell {
+    fn evaluate(&self, particle: &Particle) -> f64 {
+        if particle.squared_sum() < self.radius.powi(2) { -self.depth } else { 0. }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Vector;
+
+    #[test]
+    fn test_potentials() {
+        let tol = 1e-12;
+        let particle = Particle::from_vector(Vector::D3(1., 2., 2.));
+
+        let ho = HarmonicOscillator { omega: 2., anisotropy: [1., 1., 0.5] };
+        assert!((ho.evaluate(&particle) - 2. * (1. + 4. + 1.)).abs() < tol);
+
+        // The bottom of each well of the double well is at x = +-1
+        let double_well = DoubleWell { omega: 1., separation: 2. };
+        assert!((double_well.evaluate(&particle) - 4.).abs() < tol);
+        let mirrored = Particle::from_vector(Vector::D3(-1., 2., 2.));
+        assert!((double_well.evaluate(&mirrored) - 4.).abs() < tol);
+
+        let coulomb = CoulombCenter { charge: 2. };
+        assert!((coulomb.evaluate(&particle) + 2. / 3.).abs() < tol);
+
+        let well = FiniteWell { depth: 5., radius: 3.5 };
+        assert_eq!(well.evaluate(&particle), -5.);
+        assert_eq!(well.evaluate(&Particle::from_vector(Vector::D3(4., 0., 0.))), 0.);
+
+        let closure = |p: &Particle| p.squared_sum();
+        assert_eq!(closure.evaluate(&particle), 9.);
+    }
+}
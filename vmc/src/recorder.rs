@@ -0,0 +1,131 @@
+use crate::{montecarlo::SampledValues, utils::create_file};
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// Where the recorded time series ends up.
+enum Sink {
+    File(BufWriter<File>),
+    Memory(Vec<Vec<f64>>),
+}
+
+/// Records the time series of sampled values in `monte_carlo`, one row per cycle. Each row holds
+/// the cycle number, whether the system had reached equilibrium, whether the step was accepted,
+/// followed by every value in `SampledValues::map` in alphabetical order.
+pub struct Recorder {
+    sink: Sink,
+    thinning: usize,
+    keys: Vec<String>,
+}
+
+impl Recorder {
+    /// Streams every `thinning`'th cycle to a CSV file at `path`.
+    pub fn file(path: &Path, thinning: usize) -> Self {
+        Recorder {
+            sink: Sink::File(BufWriter::new(create_file(path))),
+            thinning: thinning.max(1),
+            keys: vec![],
+        }
+    }
+
+    /// Keeps every `thinning`'th cycle in memory.
+    pub fn memory(thinning: usize) -> Self {
+        Recorder {
+            sink: Sink::Memory(vec![]),
+            thinning: thinning.max(1),
+            keys: vec![],
+        }
+    }
+
+    /// Names of the columns, in the same order as the values of each row
+    pub fn columns(&self) -> Vec<String> {
+        let mut columns: Vec<String> = ["cycle", "equilibrated", "accepted"].iter().map(|s| s.to_string()).collect();
+        columns.extend(self.keys.iter().cloned());
+        columns
+    }
+
+    /// Returns the rows recorded so far. Empty unless recording to memory.
+    pub fn rows(&self) -> &[Vec<f64>] {
+        match &self.sink {
+            Sink::Memory(rows) => rows,
+            Sink::File(_) => &[],
+        }
+    }
+
+    /// Returns the recorded time series of a single column. Only available when recording to
+    /// memory.
+    pub fn series(&self, column: &str) -> Option<Vec<f64>> {
+        let i = self.columns().iter().position(|c| c == column)?;
+        match &self.sink {
+            Sink::Memory(rows) => Some(rows.iter().map(|row| row[i]).collect()),
+            Sink::File(_) => None,
+        }
+    }
+
+    /// Records the values of a cycle, if it is not thinned away
+    pub fn record(&mut self, cycle: usize, equilibrated: bool, accepted: bool, vals: &SampledValues) -> Result<(), String> {
+        if !cycle.is_multiple_of(self.thinning) {
+            return Ok(());
+        }
+
+        // The columns are decided by the first values we see
+        if self.keys.is_empty() {
+            self.keys = vals.map.keys().cloned().collect();
+            self.keys.sort();
+            let header = self.columns().join(",");
+            if let Sink::File(f) = &mut self.sink {
+                writeln!(f, "{}", header).map_err(|why| why.to_string())?;
+            }
+        }
+
+        let mut row = vec![cycle as f64, equilibrated as u8 as f64, accepted as u8 as f64];
+        row.extend(self.keys.iter().map(|key| *vals.map.get(key).unwrap_or(&f64::NAN)));
+
+        match &mut self.sink {
+            Sink::File(f) => {
+                let line: Vec<String> = row.iter().map(|val| val.to_string()).collect();
+                writeln!(f, "{}", line.join(",")).map_err(|why| why.to_string())
+            }
+            Sink::Memory(rows) => {
+                rows.push(row);
+                Ok(())
+            }
+        }
+    }
+
+    /// Makes sure everything recorded has been written
+    pub fn flush(&mut self) -> Result<(), String> {
+        match &mut self.sink {
+            Sink::File(f) => f.flush().map_err(|why| why.to_string()),
+            Sink::Memory(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vals(energy: f64) -> SampledValues {
+        let mut vals = SampledValues::new();
+        vals.map.insert("energy".to_string(), energy);
+        vals.map.insert("kinetic".to_string(), 0.5 * energy);
+        vals
+    }
+
+    #[test]
+    fn test_thinning() {
+        let mut recorder = Recorder::memory(3);
+        for i in 0..10 {
+            recorder.record(i, true, i % 2 == 0, &vals(i as f64)).unwrap();
+        }
+        assert_eq!(recorder.columns(), vec!["cycle", "equilibrated", "accepted", "energy", "kinetic"]);
+        assert_eq!(recorder.series("cycle").unwrap(), vec![0., 3., 6., 9.]);
+        assert_eq!(recorder.series("accepted").unwrap(), vec![1., 0., 1., 0.]);
+        assert_eq!(recorder.series("kinetic").unwrap(), vec![0., 1.5, 3., 4.5]);
+        assert!(recorder.series("distance").is_none());
+    }
+}
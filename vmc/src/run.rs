@@ -1,499 +1,594 @@
 use crate::{
-    montecarlo, BruteForceMetropolis, Hamiltonian, ImportanceMetropolis, Metropolis, System,
-    ThreadPool, WaveFunction, Particle, Vector
+    cli::SgdOptions,
+    config,
+    montecarlo,
+    optimizer::{self, Adam, ConvergenceLog, CorrelatedSampling, GradientDescent, LinearMethod, LineSearch, Method, Momentum, Objective, Optimizer, StochasticReconfiguration},
+    parameters::{Backend, Overrides, Parameters, Range, Sampler},
+    utils::{create_dir, create_file, stream_seed},
+    BruteForceMetropolis, ImportanceMetropolis, Metropolis, Particle, Recorder, System, ThreadPool,
+    TrialWaveFunction, Variational, Vector, Walkers, WaveFunction, validation,
 };
 
+use nalgebra::{allocator::Allocator, Const, DefaultAllocator, Dim, Dynamic};
 use std::{
-    env,
-    fs::{create_dir_all, File},
     io::prelude::*,
-    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
     time::Instant,
 };
 
-#[allow(dead_code)]
-pub fn simple() {
-    const ALPHA: f64 = 1.0;
-    const OMEGA: f64 = 1.0;
-    const BETA: f64 =  1.0;
-    const JASTROW: bool = false;
-    const STEP_SIZE: f64 = 0.1;
-    const MC_CYCLES: usize = 100_000;
-    const DIM: usize = 2;
-    const N: usize = 2;
-    const SPREAD: f64 = 0.1;
-
-    fn simulate<T: Metropolis>(numerical_laplace: bool, interacting: bool) {
+/// Calls `$f::<$t.., D>` where `D` sizes the Slater matrices: `Dynamic` with the dynamic backend,
+/// and `Const<N>` for the particle count `N` with the static one. `N` then has to be known at
+/// compile time, so every supported particle count is listed.
+macro_rules! dispatch_size {
+    ($f:ident::<$($t:ty,)*>($params:expr $(, $arg:expr)*)) => {
+        match ($params.backend, $params.n) {
+            (Backend::Dynamic, _) => $f::<$($t,)* Dynamic>($params $(, $arg)*),
+            (Backend::Static, 2) => $f::<$($t,)* Const<2>>($params $(, $arg)*),
+            (Backend::Static, 6) => $f::<$($t,)* Const<6>>($params $(, $arg)*),
+            (Backend::Static, 8) => $f::<$($t,)* Const<8>>($params $(, $arg)*),
+            (Backend::Static, 12) => $f::<$($t,)* Const<12>>($params $(, $arg)*),
+            (Backend::Static, 20) => $f::<$($t,)* Const<20>>($params $(, $arg)*),
+            (Backend::Static, 30) => $f::<$($t,)* Const<30>>($params $(, $arg)*),
+            (Backend::Static, 40) => $f::<$($t,)* Const<40>>($params $(, $arg)*),
+            (Backend::Static, 42) => $f::<$($t,)* Const<42>>($params $(, $arg)*),
+            (Backend::Static, 56) => $f::<$($t,)* Const<56>>($params $(, $arg)*),
+            // `Overrides::apply` only lets through particle counts in `SUPPORTED_N`
+            (Backend::Static, n) => unreachable!("N = {} is not supported", n),
+        }
+    };
+}
+
+/// Calls `$f::<T, D>` with the Metropolis algorithm `T` given by the parameters, and the size `D`
+/// of the Slater matrices as in `dispatch_size`.
+macro_rules! dispatch {
+    ($f:ident($params:expr $(, $arg:expr)*)) => {
+        match $params.sampler {
+            Sampler::BruteForce => dispatch_size!($f::<BruteForceMetropolis,>($params $(, $arg)*)),
+            Sampler::Importance => dispatch_size!($f::<ImportanceMetropolis,>($params $(, $arg)*)),
+        }
+    };
+}
+
+/// Calls `$f::<D>` with the size `D` of the Slater matrices as in `dispatch_size`, for runs that do
+/// not sample the system and so do not need a Metropolis algorithm.
+macro_rules! dispatch_n {
+    ($f:ident($params:expr $(, $arg:expr)*)) => {
+        dispatch_size!($f::<>($params $(, $arg)*))
+    };
+}
+
+pub fn simple(overrides: &Overrides, record_every: Option<usize>) -> Result<(), String> {
+    let params = overrides.apply(Parameters { jastrow: false, ..Default::default() })?;
+
+    fn simulate<T: Metropolis, D: Dim>(params: &Parameters, record_every: Option<usize>) -> Result<(), String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
         let metro_type = std::any::type_name::<T>().split("::").last().unwrap();
-        println!("Running run::simple() with {}, Numerical laplace: {:?}, Interacting: {:?}", &metro_type, &numerical_laplace, &interacting);
-        let mut metro: T = T::new(STEP_SIZE);
+        println!("Running run::simple() with {}, Numerical laplace: {:?}, Interacting: {:?}", &metro_type, &params.numerical_laplace, &params.interacting);
+        let mut metro: T = T::new(params.step_size);
 
-        let mut path = find_cargo_root().unwrap();
-        path.push("data");
-        path.push("N2");
+        let mut path = params.output.clone();
+        path.push(format!("N{}", params.n));
         create_dir(&path);
 
-        let interact_str = if interacting { "interacting" } else { "non-interacting" };
-        let numerical_str = if numerical_laplace { "numerical" } else { "analytical" };
-        path.push(format!("{}_{}_{}-with-jastrow.csv", metro_type, interact_str, numerical_str));
+        let interact_str = if params.interacting { "interacting" } else { "non-interacting" };
+        let numerical_str = if params.numerical_laplace { "numerical" } else { "analytical" };
+        let jastrow_str = if params.jastrow { "-with-jastrow" } else { "" };
+        path.push(format!("{}_{}_{}{}.csv", metro_type, interact_str, numerical_str, jastrow_str));
         let mut f = create_file(&path);
-        f.write_all("energy[au],time[s],kinetic,variance,acceptance_rate\n".as_bytes()).expect("Unable to write data");
+        config::write_next_to(params, &path)?;
+        f.write_all("energy[au],time[s],kinetic,variance,acceptance_rate,error,autocorrelation_time\n".as_bytes()).expect("Unable to write data");
 
         // Run 10 times
-        for _ in 0..10 {
+        for run in 0..10u64 {
             let start = Instant::now();
-            let wf = WaveFunction { alpha: ALPHA, beta: BETA, omega: OMEGA, jastrow_on: JASTROW }; // Set beta = gamma
-            let mut system: System<N> = System::new(N, DIM, wf, interacting, numerical_laplace, SPREAD).unwrap();
-            let vals = montecarlo::monte_carlo(MC_CYCLES, &mut system, &mut metro).unwrap();
-
-            let energy = match vals.map.get("energy") {
-                Some(val) => *val,
-                None => 0.,
-            };
-            let energy_sqrd = match vals.map.get("energy_sqrd") {
-                Some(val) => *val,
-                None => 0.,
-            };
-            let kinetic = match vals.map.get("kinetic") {
-                Some(val) => *val,
-                None => 0.,
+            let wf = WaveFunction { alpha: params.alpha, beta: params.beta, omega: params.omega, anisotropy: params.anisotropy, jastrow_on: params.jastrow }; // Set beta = gamma
+            let mut system: System<D> = System::new(params.n, params.dim, wf, params.interacting, params.numerical_laplace, params.spread, stream_seed(params.seed, run))?;
+            system.refresh = params.refresh();
+            system.hamiltonian = params.hamiltonian();
+            let vals = match record_every {
+                Some(thinning) => {
+                    let stem = path.file_stem().unwrap().to_str().unwrap();
+                    let mut recorder = Recorder::file(&path.with_file_name(format!("{}_series{}.csv", stem, run)), thinning);
+                    montecarlo::monte_carlo_recorded(params.mc_cycles, &mut system, &mut metro, &mut recorder)?
+                }
+                None => montecarlo::monte_carlo(params.mc_cycles, &mut system, &mut metro)?,
             };
 
-            let acceptance_rate = (vals.accepted_steps as f64) / (MC_CYCLES as f64);
-            let data = format!("{},{},{},{},{}\n", energy, start.elapsed().as_millis() as f64 / 1000., kinetic, energy_sqrd - energy.powi(2), acceptance_rate);
+            let energy = *vals.map.get("energy").unwrap_or(&0.);
+            let energy_sqrd = *vals.map.get("energy_sqrd").unwrap_or(&0.);
+            let kinetic = *vals.map.get("kinetic").unwrap_or(&0.);
+
+            let error = vals.error("energy").unwrap_or(0.);
+            let autocorrelation_time = vals.autocorrelation_time("energy").unwrap_or(0.);
+
+            let acceptance_rate = (vals.accepted_steps as f64) / (params.mc_cycles as f64);
+            let data = format!("{},{},{},{},{},{},{}\n", energy, start.elapsed().as_millis() as f64 / 1000., kinetic, energy_sqrd - energy.powi(2), acceptance_rate, error, autocorrelation_time);
             f.write_all(data.as_bytes()).expect("Unable to write data");
             println!("{}", data);
         }
+        Ok(())
     }
 
     let start = Instant::now();
-    let pool = ThreadPool::new(2);
-    pool.execute(move || simulate::<BruteForceMetropolis>(false, false));
-    pool.execute(move || simulate::<BruteForceMetropolis>(false, true));
-    pool.execute(move || simulate::<BruteForceMetropolis>(true, false));
-    pool.execute(move || simulate::<BruteForceMetropolis>(true, true));
-    pool.execute(move || simulate::<ImportanceMetropolis>(false, false));
-    pool.execute(move || simulate::<ImportanceMetropolis>(false, true));
-    pool.execute(move || simulate::<ImportanceMetropolis>(true, false));
-    pool.execute(move || simulate::<ImportanceMetropolis>(true, true));
-    pool.join_all();  
+    dispatch!(simulate(&params, record_every))?;
     println!("Total time spent: {:?}", start.elapsed());
+    Ok(())
 }
 
-#[allow(dead_code)]
-pub fn multiple() {
-    const JASTROW: bool = true;
-    const NUMERICAL_LAPLACE: bool = true;
-    const INTERACTING: bool = true;
-    const STEP_SIZE: f64 = 0.5;
-    const MC_CYCLES: usize = 100_000;
-    const DIM: usize = 2;
-    const SPREAD: f64 = 0.5;
-
-    fn simulate<const N: usize>(omega: f64, alpha: f64, beta: f64) {
-        let metro_type = "BruteForceMetropolis";
-        println!("Running run::simple() with {}, Numerical laplace: {:?}, Interacting: {:?}", &metro_type, NUMERICAL_LAPLACE, INTERACTING);
-        let mut metro = BruteForceMetropolis::new(STEP_SIZE);
-
-        let mut path = find_cargo_root().unwrap();
-        path.push("data");
-        path.push(format!("N{}", N));
+pub fn multiple(overrides: &Overrides, omegas: &[f64], alphas: &[f64], betas: &[f64]) -> Result<(), String> {
+    let params = overrides.apply(Parameters {
+        numerical_laplace: true,
+        step_size: 0.5,
+        spread: 0.5,
+        ..Default::default()
+    })?;
+
+    // The points we've found to be optimal for the two-particle system
+    let (omegas, alphas, betas) = if omegas.is_empty() && alphas.is_empty() && betas.is_empty() {
+        (vec![0.01, 0.05, 0.1, 0.5, 1.0], vec![0.93, 0.98, 0.97, 0.97, 0.98], vec![0.16, 0.24, 0.35, 0.38, 0.43])
+    } else {
+        (omegas.to_vec(), alphas.to_vec(), betas.to_vec())
+    };
+    if omegas.len() != alphas.len() || omegas.len() != betas.len() {
+        return Err("omegas, alphas and betas must have the same length.".to_owned());
+    }
+    // Checked before any simulation starts, rather than failing on a pool thread
+    for i in 0..omegas.len() {
+        Parameters { omega: omegas[i], alpha: alphas[i], beta: betas[i], ..params.clone() }.validate()?;
+    }
+
+    fn simulate<T: Metropolis, D: Dim>(params: &Parameters) -> Result<(), String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let metro_type = std::any::type_name::<T>().split("::").last().unwrap();
+        println!("Running run::multiple() with {}, Numerical laplace: {:?}, Interacting: {:?}", &metro_type, params.numerical_laplace, params.interacting);
+        let mut metro: T = T::new(params.step_size);
+
+        let mut path = params.output.clone();
+        path.push(format!("N{}", params.n));
         create_dir(&path);
 
-        path.push(format!("omega{}_alpha{}_beta{}_with-jastrow.csv", omega, alpha, beta));
+        let jastrow_str = if params.jastrow { "with-jastrow" } else { "without-jastrow" };
+        path.push(format!("omega{}_alpha{}_beta{}_{}.csv", params.omega, params.alpha, params.beta, jastrow_str));
         let mut f = create_file(&path);
-        f.write_all("energy[au],time[s],kinetic,variance,acceptance_rate,avg_distance\n".as_bytes()).expect("Unable to write data");
+        config::write_next_to(params, &path)?;
+        f.write_all("energy[au],time[s],kinetic,variance,acceptance_rate,avg_distance,error,autocorrelation_time\n".as_bytes()).expect("Unable to write data");
 
         let start = Instant::now();
-        let wf = WaveFunction { alpha, beta, omega, jastrow_on: JASTROW }; // Set beta = gamma
-        let mut system: System<N> = System::new(N, DIM, wf, INTERACTING, NUMERICAL_LAPLACE, SPREAD).unwrap();
-        let vals = montecarlo::monte_carlo(MC_CYCLES, &mut system, &mut metro).unwrap();
-
-        let energy = match vals.map.get("energy") {
-            Some(val) => *val,
-            None => 0.,
-        };
-        let energy_sqrd = match vals.map.get("energy_sqrd") {
-            Some(val) => *val,
-            None => 0.,
-        };
-        let kinetic = match vals.map.get("kinetic") {
-            Some(val) => *val,
-            None => 0.,
-        };
-        let distance = match vals.map.get("distance") {
-            Some(val) => *val,
-            None => 0.,
-        };
-
-        let acceptance_rate = (vals.accepted_steps as f64) / (MC_CYCLES as f64);
-        let data = format!("{},{},{},{},{},{}\n", energy, start.elapsed().as_millis() as f64 / 1000., kinetic, energy_sqrd - energy.powi(2), acceptance_rate, distance);
+        let wf = WaveFunction { alpha: params.alpha, beta: params.beta, omega: params.omega, anisotropy: params.anisotropy, jastrow_on: params.jastrow }; // Set beta = gamma
+        let mut system: System<D> = System::new(params.n, params.dim, wf, params.interacting, params.numerical_laplace, params.spread, params.seed)?;
+        system.refresh = params.refresh();
+        system.hamiltonian = params.hamiltonian();
+        let vals = montecarlo::monte_carlo(params.mc_cycles, &mut system, &mut metro)?;
+
+        let energy = *vals.map.get("energy").unwrap_or(&0.);
+        let energy_sqrd = *vals.map.get("energy_sqrd").unwrap_or(&0.);
+        let kinetic = *vals.map.get("kinetic").unwrap_or(&0.);
+        let distance = *vals.map.get("distance").unwrap_or(&0.);
+
+        let error = vals.error("energy").unwrap_or(0.);
+        let autocorrelation_time = vals.autocorrelation_time("energy").unwrap_or(0.);
+
+        let acceptance_rate = (vals.accepted_steps as f64) / (params.mc_cycles as f64);
+        let data = format!("{},{},{},{},{},{},{},{}\n", energy, start.elapsed().as_millis() as f64 / 1000., kinetic, energy_sqrd - energy.powi(2), acceptance_rate, distance, error, autocorrelation_time);
         f.write_all(data.as_bytes()).expect("Unable to write data");
         println!("{}", data);
+        Ok(())
     }
 
     let start = Instant::now();
-    let pool = ThreadPool::new(5);
-    pool.execute(move || simulate::<2>(0.01, 0.93, 0.16));
-    pool.execute(move || simulate::<2>(0.05, 0.98, 0.24));
-    pool.execute(move || simulate::<2>(0.1, 0.97, 0.35));
-    pool.execute(move || simulate::<2>(0.5, 0.97, 0.38));
-    pool.execute(move || simulate::<2>(1.0, 0.98, 0.43));
-    pool.join_all();  
+    // The jobs have no way to hand errors back, so they report them here
+    let failures = Arc::new(Mutex::new(0));
+    let pool = ThreadPool::with_seed(omegas.len().min(u8::MAX as usize) as u8, params.seed)?;
+    for i in 0..omegas.len() {
+        let params = Parameters { omega: omegas[i], alpha: alphas[i], beta: betas[i], ..params.clone() };
+        let failures = failures.clone();
+        pool.execute_seeded(move |seed| {
+            if let Err(e) = dispatch!(simulate(&Parameters { seed, ..params.clone() })) {
+                eprintln!("Run at omega = {}, alpha = {}, beta = {} failed: {}", params.omega, params.alpha, params.beta, e);
+                *failures.lock().unwrap() += 1;
+            }
+        });
+    }
+    pool.join_all();
     println!("Total time spent: {:?}", start.elapsed());
+
+    let failures = *failures.lock().unwrap();
+    if failures > 0 {
+        return Err(format!("{} of {} runs failed.", failures, omegas.len()));
+    }
+    Ok(())
 }
 
-#[allow(dead_code)]
-pub fn sgd(interacting: bool) {
-    const ALPHA: f64 = 1.0;
-    const OMEGA: f64 = 1.0;
-    const BETA: f64 = 0.;
-    const JASTROW: bool = true;
-    const STEP_SIZE: f64 = 0.1;
-    const MC_CYCLES: usize = 200_000;
-    const DIM: usize = 2;
-    const N: usize = 2;
-    const SPREAD: f64 = 0.1;
-    const NUMERICAL_LAPLACE: bool = true;
-    const TOLERANCE: f64 = 0.00001;
-
-    fn simulate<T: Metropolis>(start_alpha:f64, start_beta:f64, learning_rate: f64, numerical_laplace: bool, interacting: bool) {
-        let metro_type = std::any::type_name::<T>().split("::").last().unwrap();
-        println!("Running run::sgd() with {}, Numerical laplace: {:?}, Interacting: {:?}, Start Alpha: {}, Start Beta: {}, Learning Rate: {}", &metro_type, &numerical_laplace, &interacting, &start_alpha, &start_beta, &learning_rate);
-        let mut alphas:Vec<f64> = vec![];
-        alphas.push(start_alpha);
-        let mut betas:Vec<f64> = vec![];
-        betas.push(start_beta);
+/// Returns the optimizer chosen in `sgd`
+fn optimizer(sgd: &SgdOptions) -> Box<dyn Optimizer> {
+    match sgd.optimizer {
+        Method::GradientDescent => Box::new(GradientDescent { learning_rate: sgd.learning_rate }),
+        Method::StochasticReconfiguration => Box::new(StochasticReconfiguration {
+            time_step: sgd.learning_rate,
+            regularization: sgd.regularization,
+        }),
+        Method::Momentum => Box::new(Momentum::new(sgd.learning_rate, sgd.momentum)),
+        Method::Adam => Box::new(Adam::new(sgd.learning_rate)),
+        Method::LineSearch => Box::new(LineSearch::new(sgd.learning_rate)),
+        Method::Linear => Box::new(LinearMethod { stabilization: sgd.stabilization, max_step: sgd.max_step }),
+    }
+}
 
-        let mut metro: T = T::new(STEP_SIZE);
+pub fn scan(overrides: &Overrides, omegas: Option<Range>, alphas: Option<Range>, betas: Option<Range>, threads: Option<u8>) -> Result<(), String> {
+    let params = overrides.apply(Parameters {
+        alpha: 0.98,
+        beta: 0.43,
+        step_size: 0.5,
+        spread: 0.5,
+        ..Default::default()
+    })?;
+
+    let values = |range: Option<Range>, default: f64| range.map_or(vec![default], |range| range.values());
+    let mut points = vec![];
+    for &omega in values(omegas, params.omega).iter() {
+        for &alpha in values(alphas, params.alpha).iter() {
+            for beta in values(betas, params.beta) {
+                points.push((omega, alpha, beta));
+            }
+        }
+    }
+    // Checked before any simulation starts, rather than failing halfway through the grid
+    for &(omega, alpha, beta) in points.iter() {
+        Parameters { omega, alpha, beta, ..params.clone() }.validate()?;
+    }
 
-        let mut done: bool = false;
-        let mut energies:Vec<f64> = vec![];
+    /// Returns the row of the point given by `params`
+    fn simulate<T: Metropolis, D: Dim>(params: &Parameters) -> Result<String, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let mut metro: T = T::new(params.step_size);
+        let wf = WaveFunction { alpha: params.alpha, beta: params.beta, omega: params.omega, anisotropy: params.anisotropy, jastrow_on: params.jastrow }; // Set beta = gamma
+        let mut system: System<D> = System::new(params.n, params.dim, wf, params.interacting, params.numerical_laplace, params.spread, params.seed)?;
+        system.refresh = params.refresh();
+        system.hamiltonian = params.hamiltonian();
+        let vals = montecarlo::monte_carlo(params.mc_cycles, &mut system, &mut metro)?;
+
+        let energy = *vals.map.get("energy").ok_or_else(|| "No energy was sampled.".to_owned())?;
+        let variance = vals.map["energy_sqrd"] - energy.powi(2);
+        let error = vals.error("energy").unwrap_or(f64::NAN);
+        let acceptance_rate = (vals.accepted_steps as f64) / (params.mc_cycles as f64);
+        Ok(format!("{},{},{},{},{},{},{}\n", params.omega, params.alpha, params.beta, energy, error, variance, acceptance_rate))
+    }
 
-        let mut path = find_cargo_root().unwrap();
-        path.push("data"); path.push("sgd"); path.push("start_params");
-        create_dir(&path);
-        path.push(format!("a-{}_b-{}.csv", start_alpha, start_beta));
-        let mut f = create_file(&path);
-        f.write_all("alpha,beta,energy-per-particle[au],time[s],variance\n".as_bytes()).expect("Unable to write data");
+    if threads == Some(0) {
+        return Err("The scan needs at least one thread.".to_owned());
+    }
+    // More threads than points would only sit idle
+    let threads = threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get().min(u8::MAX as usize) as u8));
+    let threads = threads.min(points.len().min(u8::MAX as usize) as u8);
+    println!("Running run::scan() over {} points on {} threads with {:?} sampling, Numerical laplace: {:?}, Interacting: {:?}", points.len(), threads, params.sampler, params.numerical_laplace, params.interacting);
 
-        let mut i:usize = 0;
-        while !done {
-            let start = Instant::now();
-            let wf = WaveFunction { alpha: alphas[i], beta: betas[i], omega: OMEGA, jastrow_on: JASTROW }; // Set beta = gamma
-            let mut system: System<N> = System::new(N, DIM, wf, interacting, numerical_laplace, SPREAD).unwrap();
-            let vals = montecarlo::monte_carlo(MC_CYCLES, &mut system, &mut metro).unwrap();
+    let start = Instant::now();
+    let rows = Arc::new(Mutex::new(vec![]));
+    let pool = ThreadPool::with_seed(threads, params.seed)?;
+    for (i, &(omega, alpha, beta)) in points.iter().enumerate() {
+        let params = Parameters { omega, alpha, beta, ..params.clone() };
+        let rows = rows.clone();
+        pool.execute_seeded(move |seed| {
+            let row = dispatch!(simulate(&Parameters { seed, ..params.clone() }));
+            rows.lock().unwrap().push((i, row));
+        });
+    }
+    pool.join_all();
 
-            let energy = match vals.map.get("energy") {
-                Some(val) => *val,
-                None => 0.,
-            };
-            let energy_sqrd = match vals.map.get("energy_sqrd") {
-                Some(val) => *val,
-                None => 0.,
-            };
-            let wf_deriv_alpha = match vals.map.get("wf_deriv_alpha") {
-                Some(val) => *val,
-                None => 0.,
-            };
-            let wf_deriv_alpha_times_energy = match vals.map.get("wf_deriv_alpha_times_energy") {
-                Some(val) => *val,
-                None => 0.,
-            };
-            let wf_deriv_beta = match vals.map.get("wf_deriv_beta") {
-                Some(val) => *val,
-                None => 0.,
-            };
-            let wf_deriv_beta_times_energy = match vals.map.get("wf_deriv_beta_times_energy") {
-                Some(val) => *val,
-                None => 0.,
-            };
+    // The rows come in as the points finish, so they are put back in the order of the grid
+    let mut rows = rows.lock().unwrap().clone();
+    rows.sort_by_key(|(i, _)| *i);
+    if rows.len() < points.len() {
+        return Err(format!("Only {} of {} points finished.", rows.len(), points.len()));
+    }
+    let rows = rows.into_iter().map(|(_, row)| row).collect::<Result<Vec<String>, String>>()?;
+
+    let mut path = params.output.clone();
+    path.push("scan");
+    create_dir(&path);
+    let jastrow_str = if params.jastrow { "with-jastrow" } else { "without-jastrow" };
+    path.push(format!("N{}_{}.csv", params.n, jastrow_str));
+    let mut f = create_file(&path);
+    config::write_next_to(&params, &path)?;
+    f.write_all("omega,alpha,beta,energy[au],error,variance,acceptance_rate\n".as_bytes()).expect("Unable to write data");
+    for row in rows {
+        f.write_all(row.as_bytes()).expect("Unable to write data");
+    }
 
-            let data = format!("{},{},{},{},{}\n",alphas[i], betas[i], energy / N as f64, start.elapsed().as_millis() as f64 / 1000., energy_sqrd - energy.powi(2));
-            //println!("{}", data);
-            f.write_all(data.as_bytes()).expect("Unable to write data");
-            println!("a: {:.8} || b: {:.8} || E: {:.8} || Iter: {}", alphas[i], betas[i], energy / N as f64, i);
+    println!("Wrote {} points to {} in {:?}", points.len(), path.display(), start.elapsed());
+    Ok(())
+}
 
+/// Optimizes alpha and beta, writing the path taken to `filename`, and the full convergence log
+/// next to it
+fn optimize_parameters<T: Metropolis, D: Dim>(params: &Parameters, sgd: &SgdOptions, filename: &str) -> Result<(), String>
+where
+    DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+{
+    let metro_type = std::any::type_name::<T>().split("::").last().unwrap();
+    println!("Minimizing {:?} with {:?} and {}, Numerical laplace: {:?}, Interacting: {:?}, Start Alpha: {}, Start Beta: {}, Learning Rate: {}", &sgd.objective, &sgd.optimizer, &metro_type, &params.numerical_laplace, &params.interacting, &params.alpha, &params.beta, &sgd.learning_rate);
+    let wf = WaveFunction { alpha: params.alpha, beta: params.beta, omega: params.omega, anisotropy: params.anisotropy, jastrow_on: params.jastrow }; // Set beta = gamma
+
+    let mut metro: T = T::new(params.step_size);
+
+    let mut path = params.output.clone();
+    path.push("sgd");
+    path.push(filename);
+    create_dir(path.parent().unwrap());
+    config::write_next_to(params, &path)?;
+    let stem = path.file_stem().unwrap().to_str().unwrap();
+    let mut log = ConvergenceLog::file(&path.with_file_name(format!("{}_convergence.csv", stem)));
+
+    let new_system = |wf, i: usize| {
+        let mut system: System<D> = System::new(params.n, params.dim, wf, params.interacting, params.numerical_laplace, params.spread, stream_seed(params.seed, i as u64))?;
+        system.refresh = params.refresh();
+        system.hamiltonian = params.hamiltonian();
+        Ok(system)
+    };
+    let stopping = sgd.stopping();
+    let optimized = if sgd.objective == Objective::Energy {
+        optimizer::optimize(wf, new_system, &mut metro, params.mc_cycles, optimizer(sgd).as_mut(), &stopping, &mut log)?
+    } else {
+        // One configuration every sweep over the particles, for about as many steps as `optimize` takes
+        let settings = CorrelatedSampling {
+            objective: sgd.objective,
+            walkers: params.mc_cycles / params.n,
+            interval: params.n,
+            learning_rate: sgd.learning_rate,
+            max_updates: sgd.max_updates,
+        };
+        optimizer::minimize_correlated(wf, new_system, &mut metro, &settings, &stopping, &mut log)?
+    };
+
+    let mut f = create_file(&path);
+    f.write_all("alpha,beta,energy-per-particle[au],time[s],variance\n".as_bytes()).map_err(|why| why.to_string())?;
+    for iteration in log.iterations() {
+        // Without the Jastrow factor beta is not optimized, and stays at its start value
+        let beta = iteration.parameters.get(1).copied().unwrap_or(params.beta);
+        let data = format!("{},{},{},{},{}\n", iteration.parameters[0], beta, iteration.energy / params.n as f64, iteration.time, iteration.variance);
+        f.write_all(data.as_bytes()).map_err(|why| why.to_string())?;
+    }
 
-            let energy_deriv_alpha = 2.* (wf_deriv_alpha_times_energy-wf_deriv_alpha*energy);
-            let new_alpha: f64 = alphas[i] - learning_rate * energy_deriv_alpha;
-            alphas.push(new_alpha);
+    let last = log.iterations().last().ok_or_else(|| "No iterations were run.".to_owned())?;
+    let status = if optimized.converged { "Converged" } else { "Max iter lim met" };
+    println!("{} after {} iterations: {:?} = {:?} || E: {:.8}", status, optimized.iterations, optimized.wf.parameter_names(), last.parameters, last.energy / params.n as f64);
+    Ok(())
+}
 
-            let energy_deriv_beta = 2.* (wf_deriv_beta_times_energy-wf_deriv_beta*energy);
-            let new_beta: f64 = betas[i] - learning_rate * energy_deriv_beta;
-            betas.push(new_beta);
+/// Runs `optimize_parameters` on a pool thread, where a failure can only be reported
+fn optimize_start_point<T: Metropolis, D: Dim>(params: &Parameters, sgd: &SgdOptions, filename: &str)
+where
+    DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+{
+    if let Err(e) = optimize_parameters::<T, D>(params, sgd, filename) {
+        eprintln!("Optimization from alpha = {}, beta = {}, omega = {} failed: {}", params.alpha, params.beta, params.omega, e);
+    }
+}
 
-            if energy_deriv_alpha.abs() < TOLERANCE && energy_deriv_beta.abs() < TOLERANCE {
-                println!("Tolerance is met, exiting.");
-                done = true;
-            } else if i > 150 {
-                println!("Max iter lim met, exiting.");
-                done = true;
-            }
-            //if (energies[i]-energies[i-1]).abs() < tolerance {
-            //    done = true;
-            //}
-            i += 1;
-        }
-        
+pub fn sgd(overrides: &Overrides, sgd: &SgdOptions, start_alphas: &[f64], start_betas: &[f64]) -> Result<(), String> {
+    let params = overrides.apply(Parameters {
+        beta: 0.,
+        numerical_laplace: true,
+        mc_cycles: 200_000,
+        ..Default::default()
+    })?;
+    if sgd.max_iterations == 0 || sgd.patience == 0 {
+        return Err("max_iterations and patience must be at least 1.".to_owned());
     }
+
+    let start_alphas = if start_alphas.is_empty() { vec![0.4, 0.6, 0.8, 1.0, 1.2, 1.4, 1.6, 1.8] } else { start_alphas.to_vec() };
+    let start_betas = if start_betas.is_empty() { vec![0.4, 0.6, 0.8, 1.0, 1.2, 1.4, 1.6, 1.8] } else { start_betas.to_vec() };
+
     let start = Instant::now();
-    //simulate::<BruteForceMetropolis>(0.5 ,1. , 0.05, true, interacting);
-
-    
-    // Multithreading
-    println!("Running simulations using BruteForceMetropolis algorithm...");
-    let start_alphas:Vec<f64> = vec![0.4, 0.6, 0.8, 1.0, 1.2, 1.4, 1.6, 1.8];
-    let start_betas: Vec<f64> = vec![0.4, 0.6, 0.8, 1.0, 1.2, 1.4, 1.6, 1.8];
-    let learning_rates:Vec<f64> = vec![0.00005, 0.0001, 0.0002, 0.0004, 0.0008, 0.0016, 0.0032, 0.0064];
-    let start_alpha: f64 = 0.0;
-    let start_beta: f64 = 0.0;
-    let learning_rate: f64 = 0.05; //0.0004 was the chosen one for project 1
-
-    println!("Spawning threadpool of 8 threads, with {} Monte Carlo cycles on each", &MC_CYCLES);
-    
-
-    
-    for start_alpha in start_alphas {
-    //for learning_rate in learning_rates {
-        let pool = ThreadPool::new(8);
+    println!("Spawning threadpool of {} threads, with {} Monte Carlo cycles on each", start_betas.len(), params.mc_cycles);
+
+    for (i, start_alpha) in start_alphas.into_iter().enumerate() {
+        let pool = ThreadPool::with_seed(start_betas.len().min(u8::MAX as usize) as u8, stream_seed(params.seed, i as u64))?;
         let start = Instant::now();
 
-        for start_beta in start_betas.clone() {
-            pool.execute(move || simulate::<BruteForceMetropolis>(start_alpha, start_beta, learning_rate, true, interacting)); //Running the simulation on each thread individually
+        for &start_beta in start_betas.iter() {
+            let params = Parameters { alpha: start_alpha, beta: start_beta, ..params.clone() };
+            let sgd = sgd.clone();
+            let filename = format!("start_params/a-{}_b-{}.csv", start_alpha, start_beta);
+            pool.execute_seeded(move |seed| dispatch!(optimize_start_point(&Parameters { seed, ..params.clone() }, &sgd, &filename))); //Running the simulation on each thread individually
         }
         println!("All threads now executing with different betas and alpha = {} , waiting for them to finish...", &start_alpha);
         pool.join_all();
         println!("Time spent on all betas for alpha = {}: {:?}", &start_alpha, start.elapsed());
     }
-    
-    
+
     println!("Total time spent: {:?}", start.elapsed());
+    Ok(())
 }
 
-#[allow(dead_code)]
-pub fn sgd_omega(interacting: bool) {
-    const ALPHA: f64 = 0.5;
-    const OMEGA: f64 = 1.0;
-    const BETA: f64 = 0.5;
-    const JASTROW: bool = true;
-    const STEP_SIZE: f64 = 0.1;
-    const MC_CYCLES: usize = 200_000;
-    const DIM: usize = 2;
-    const N: usize = 2;
-    const SPREAD: f64 = 0.1;
-    const NUMERICAL_LAPLACE: bool = true;
-    const TOLERANCE: f64 = 0.00001;
-
-    fn simulate<T: Metropolis>(omega:f64, start_alpha:f64, start_beta:f64, learning_rate: f64, numerical_laplace: bool, interacting: bool) {
-        let metro_type = std::any::type_name::<T>().split("::").last().unwrap();
-        println!("Running run::sgd_omega() with {}, Numerical laplace: {:?}, Interacting: {:?}, Start Alpha: {}, Start Beta: {}, Learning Rate: {}", &metro_type, &numerical_laplace, &interacting, &start_alpha, &start_beta, &learning_rate);
-        let mut alphas:Vec<f64> = vec![];
-        alphas.push(start_alpha);
-        let mut betas:Vec<f64> = vec![];
-        betas.push(start_beta);
-
-        let mut metro: T = T::new(STEP_SIZE);
-
-        let mut done: bool = false;
-        let mut energies:Vec<f64> = vec![];
-
-        let mut path = find_cargo_root().unwrap();
-        path.push("data"); path.push("sgd"); path.push("omega");
-        create_dir(&path);
-        path.push(format!("o-{}.csv", omega));
-        let mut f = create_file(&path);
-        f.write_all("alpha,beta,energy-per-particle[au],time[s],variance\n".as_bytes()).expect("Unable to write data");
-
-        let mut i:usize = 0;
-        while !done {
-            let start = Instant::now();
-            let wf = WaveFunction { alpha: alphas[i], beta: betas[i], omega: omega, jastrow_on: JASTROW }; // Set beta = gamma
-            let mut system: System<N> = System::new(N, DIM, wf, interacting, numerical_laplace, SPREAD).unwrap();
-            let vals = montecarlo::monte_carlo(MC_CYCLES, &mut system, &mut metro).unwrap();
-
-            let energy = match vals.map.get("energy") {
-                Some(val) => *val,
-                None => 0.,
-            };
-            let energy_sqrd = match vals.map.get("energy_sqrd") {
-                Some(val) => *val,
-                None => 0.,
-            };
-            let wf_deriv_alpha = match vals.map.get("wf_deriv_alpha") {
-                Some(val) => *val,
-                None => 0.,
-            };
-            let wf_deriv_alpha_times_energy = match vals.map.get("wf_deriv_alpha_times_energy") {
-                Some(val) => *val,
-                None => 0.,
-            };
-            let wf_deriv_beta = match vals.map.get("wf_deriv_beta") {
-                Some(val) => *val,
-                None => 0.,
-            };
-            let wf_deriv_beta_times_energy = match vals.map.get("wf_deriv_beta_times_energy") {
-                Some(val) => *val,
-                None => 0.,
-            };
-
-            let data = format!("{},{},{},{},{}\n",alphas[i], betas[i], energy / N as f64, start.elapsed().as_millis() as f64 / 1000., energy_sqrd - energy.powi(2));
-            //println!("{}", data);
-            f.write_all(data.as_bytes()).expect("Unable to write data");
-            println!("a: {:.8} || b: {:.8} || E: {:.8} || Iter: {}", alphas[i], betas[i], energy / N as f64, i);
-
-
-            let energy_deriv_alpha = 2.* (wf_deriv_alpha_times_energy-wf_deriv_alpha*energy);
-            let new_alpha: f64 = alphas[i] - learning_rate * energy_deriv_alpha;
-            alphas.push(new_alpha);
-
-            let energy_deriv_beta = 2.* (wf_deriv_beta_times_energy-wf_deriv_beta*energy);
-            let new_beta: f64 = betas[i] - learning_rate * energy_deriv_beta;
-            betas.push(new_beta);
-
-            if energy_deriv_alpha.abs() < TOLERANCE && energy_deriv_beta.abs() < TOLERANCE {
-                println!("Tolerance is met, exiting.");
-                done = true;
-            } else if i > 150 {
-                println!("Max iter lim met, exiting.");
-                done = true;
-            }
-            //if (energies[i]-energies[i-1]).abs() < tolerance {
-            //    done = true;
-            //}
-            i += 1;
-        }
-        
+pub fn sgd_omega(overrides: &Overrides, sgd: &SgdOptions, omegas: &[f64]) -> Result<(), String> {
+    let params = overrides.apply(Parameters {
+        alpha: 0.5,
+        beta: 0.5,
+        numerical_laplace: true,
+        mc_cycles: 200_000,
+        ..Default::default()
+    })?;
+    if sgd.max_iterations == 0 || sgd.patience == 0 {
+        return Err("max_iterations and patience must be at least 1.".to_owned());
     }
-    let start = Instant::now();
-    //simulate::<BruteForceMetropolis>(0.5 ,1. , 0.05, true, interacting);
 
-    
-    // Multithreading
-    println!("Running simulations using BruteForceMetropolis algorithm...");
-    let omegas:Vec<f64> = vec![1.0, 0.5, 0.1, 0.05, 0.01];
-    let learning_rate: f64 = 0.05; //0.0004 was the chosen one for project 1
+    let omegas = if omegas.is_empty() { vec![1.0, 0.5, 0.1, 0.05, 0.01] } else { omegas.to_vec() };
 
-    println!("Spawning threadpool of 5 threads, with {} Monte Carlo cycles on each", &MC_CYCLES);
-    
-    let pool = ThreadPool::new(5);
+    let start = Instant::now();
+    println!("Spawning threadpool of {} threads, with {} Monte Carlo cycles on each", omegas.len(), params.mc_cycles);
 
+    let pool = ThreadPool::with_seed(omegas.len().min(u8::MAX as usize) as u8, params.seed)?;
     for omega in omegas {
-        pool.execute(move || simulate::<BruteForceMetropolis>( omega,ALPHA, BETA, learning_rate, true, interacting)); //Running the simulation on each thread individually
+        let params = Parameters { omega, ..params.clone() };
+        let sgd = sgd.clone();
+        let filename = format!("omega/o-{}.csv", omega);
+        pool.execute_seeded(move |seed| dispatch!(optimize_start_point(&Parameters { seed, ..params.clone() }, &sgd, &filename))); //Running the simulation on each thread individually
     }
 
     println!("All threads now executing with different omegas, waiting for them to finish...");
     pool.join_all();
-    
+
     println!("Total time spent: {:?}", start.elapsed());
+    Ok(())
 }
 
+pub fn onebody(overrides: &Overrides) -> Result<(), String> {
+    let params = overrides.apply(Parameters {
+        alpha: 0.98,
+        beta: 0.43,
+        numerical_laplace: true,
+        ..Default::default()
+    })?;
 
-#[allow(dead_code)]
-pub fn onebody() {
-    const ALPHA: f64 = 0.98;
-    const OMEGA: f64 = 1.0;
-    const BETA: f64 =  0.43;
-    const STEP_SIZE: f64 = 0.1;
-    const MC_CYCLES: usize = 100_000;
-    const DIM: usize = 2;
-    const N: usize = 2;
-    const SPREAD: f64 = 0.1;
+    if params.n != 2 || params.dim != 2 {
+        return Err("run::onebody only supports two particles in two dimensions.".to_owned());
+    }
 
-    fn simulate<T: Metropolis>(jastrow: bool) {
-        let metro_type = std::any::type_name::<T>().split("::").last().unwrap();
-        let mut metro: T = T::new(STEP_SIZE);
+    let start = Instant::now();
 
-        let mut path = find_cargo_root().unwrap();
-        path.push("data");
-        path.push("N2");
-        create_dir(&path);
+    let mut path = params.output.clone();
+    path.push("N2");
+    create_dir(&path);
 
-        let jastrow_str = if jastrow { "with-jastro" } else { "without-jastro" };
-        path.push(format!("onebody_{}.csv", jastrow_str));
-        let mut f = create_file(&path);
-        f.write_all("distance,wf_squared\n".as_bytes()).expect("Unable to write data");
+    let jastrow_str = if params.jastrow { "with-jastro" } else { "without-jastro" };
+    path.push(format!("onebody_{}.csv", jastrow_str));
+    let mut f = create_file(&path);
+    config::write_next_to(&params, &path)?;
+    f.write_all("distance,wf_squared\n".as_bytes()).expect("Unable to write data");
 
-        let wf = WaveFunction { alpha: ALPHA, beta: BETA, omega: OMEGA, jastrow_on: jastrow };
-        let system: System<N> = System::new(N, DIM, wf, true, true, SPREAD).unwrap();
-        // Run 10 times
-        for i in 0..100 {
-            let distance = 2. / 100. * (i as f64 + 1.);
-            let particles = vec![
-                Particle::from_vector(Vector::D2((distance / 2.).sqrt(), (distance / 2.).sqrt())),
-                Particle::from_vector(Vector::D2(-(distance / 2.).sqrt(), -(distance / 2.).sqrt())),
-            ];
+    let wf = WaveFunction { alpha: params.alpha, beta: params.beta, omega: params.omega, anisotropy: params.anisotropy, jastrow_on: params.jastrow };
+    let system: System<Const<2>> = System::new(2, params.dim, wf, params.interacting, params.numerical_laplace, params.spread, params.seed)?;
+    for i in 0..100 {
+        let distance = 2. / 100. * (i as f64 + 1.);
+        let particles = vec![
+            Particle::from_vector(Vector::D2((distance / 2.).sqrt(), (distance / 2.).sqrt())),
+            Particle::from_vector(Vector::D2(-(distance / 2.).sqrt(), -(distance / 2.).sqrt())),
+        ];
 
-            let wf_squared = system.wf.evaluate::<N>(&particles).unwrap();
+        let wf_squared = system.wf.evaluate(&particles)?;
 
-            let data = format!("{},{}\n", distance, wf_squared);
-            f.write_all(data.as_bytes()).expect("Unable to write data");
-            println!("{}", data);
-        }
+        let data = format!("{},{}\n", distance, wf_squared);
+        f.write_all(data.as_bytes()).expect("Unable to write data");
+        println!("{}", data);
     }
 
-    let start = Instant::now();
-    let pool = ThreadPool::new(2);
-    pool.execute(move || simulate::<BruteForceMetropolis>(false));
-    pool.execute(move || simulate::<BruteForceMetropolis>(true));
-    pool.join_all();  
     println!("Total time spent: {:?}", start.elapsed());
+    Ok(())
 }
 
-
-#[allow(dead_code)]
-pub fn performance() {
-    const ALPHA: f64 = 0.98;
-    const OMEGA: f64 = 1.0;
-    const BETA: f64 =  0.43;
-    const JASTROW: bool = true;
-    const STEP_SIZE: f64 = 0.1;
-    const MC_CYCLES: usize = 1_000_000;
-    const DIM: usize = 2;
-    const N: usize = 2;
-    const SPREAD: f64 = 0.1;
-
-    fn simulate<T: Metropolis>(numerical_laplace: bool, interacting: bool) {
-        let mut metro: T = T::new(STEP_SIZE);
-        let wf = WaveFunction { alpha: ALPHA, beta: BETA, omega: OMEGA, jastrow_on: JASTROW }; // Set beta = gamma
-        let mut system: System<N> = System::new(N, DIM, wf, interacting, numerical_laplace, SPREAD).unwrap();
-        let _ = montecarlo::monte_carlo(MC_CYCLES, &mut system, &mut metro).unwrap();
+pub fn performance(overrides: &Overrides) -> Result<(), String> {
+    let params = overrides.apply(Parameters {
+        alpha: 0.98,
+        beta: 0.43,
+        numerical_laplace: true,
+        mc_cycles: 1_000_000,
+        ..Default::default()
+    })?;
+
+    fn simulate<T: Metropolis, D: Dim>(params: &Parameters) -> Result<(), String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let mut metro: T = T::new(params.step_size);
+        let wf = WaveFunction { alpha: params.alpha, beta: params.beta, omega: params.omega, anisotropy: params.anisotropy, jastrow_on: params.jastrow }; // Set beta = gamma
+        let mut system: System<D> = System::new(params.n, params.dim, wf, params.interacting, params.numerical_laplace, params.spread, params.seed)?;
+        system.refresh = params.refresh();
+        system.hamiltonian = params.hamiltonian();
+        montecarlo::monte_carlo(params.mc_cycles, &mut system, &mut metro)?;
+        Ok(())
     }
 
     let start = Instant::now();
-    simulate::<BruteForceMetropolis>(true, true);
+    dispatch!(simulate(&params))?;
     println!("Total time spent: {:?}", start.elapsed());
+    Ok(())
 }
 
+pub fn reweight(overrides: &Overrides, alphas: &[f64], betas: &[f64]) -> Result<(), String> {
+    let params = overrides.apply(Parameters {
+        alpha: 0.98,
+        beta: 0.43,
+        step_size: 0.5,
+        spread: 0.5,
+        mc_cycles: 1_000_000,
+        ..Default::default()
+    })?;
+
+    // Eleven points around the sampled ones in steps of 0.02 unless given, rounded to keep the
+    // values in the file readable
+    let around = |center: f64, given: &[f64]| if given.is_empty() { (-5..=5).map(|i| ((center + 0.02 * i as f64) * 1e6).round() / 1e6).collect() } else { given.to_vec() };
+    // Without the Jastrow factor the energy does not depend on beta
+    if !params.jastrow && !betas.is_empty() {
+        return Err("betas have no effect without the Jastrow factor.".to_owned());
+    }
+    let alphas = around(params.alpha, alphas);
+    let betas = if params.jastrow { around(params.beta, betas) } else { vec![params.beta] };
 
+    fn simulate<T: Metropolis, D: Dim>(params: &Parameters, alphas: &[f64], betas: &[f64]) -> Result<(), String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let metro_type = std::any::type_name::<T>().split("::").last().unwrap();
+        println!("Running run::reweight() with {}, sampled at alpha = {}, beta = {}", &metro_type, params.alpha, params.beta);
+        let mut metro: T = T::new(params.step_size);
 
-fn find_cargo_root() -> Option<PathBuf> {
-    let mut path: PathBuf = env::current_dir().unwrap().into();
-    let file = Path::new("Cargo.toml");
-
-    loop {
-        path.push(file);
-
-        if path.is_file() {
-            path.pop();
-            break Some(path);
-        }
+        let mut path = params.output.clone();
+        path.push("reweight");
+        create_dir(&path);
+        path.push(format!("N{}_omega{}_alpha{}_beta{}.csv", params.n, params.omega, params.alpha, params.beta));
+        let mut f = create_file(&path);
+        config::write_next_to(params, &path)?;
+        f.write_all("alpha,beta,energy[au],error,variance,effective_sample_size\n".as_bytes()).expect("Unable to write data");
 
-        if !(path.pop() && path.pop()) {
-            break None;
+        let start = Instant::now();
+        let wf = WaveFunction { alpha: params.alpha, beta: params.beta, omega: params.omega, anisotropy: params.anisotropy, jastrow_on: params.jastrow }; // Set beta = gamma
+        let mut system: System<D> = System::new(params.n, params.dim, wf, params.interacting, params.numerical_laplace, params.spread, params.seed)?;
+        system.refresh = params.refresh();
+        system.hamiltonian = params.hamiltonian();
+        // One configuration every sweep over the particles
+        let walkers = Walkers::sample(&mut system, &mut metro, params.mc_cycles / params.n, params.n)?;
+        println!("Sampled {} configurations in {:?}", walkers.len(), start.elapsed());
+
+        for &alpha in alphas {
+            for &beta in betas {
+                let parameters = if params.jastrow { vec![alpha, beta] } else { vec![alpha] };
+                system.wf.set_parameters(&parameters)?;
+                let reweighted = walkers.reweight_energy(&mut system)?;
+                let data = format!("{},{},{},{},{},{}\n", alpha, beta, reweighted.energy, reweighted.error, reweighted.variance, reweighted.effective_sample_size);
+                f.write_all(data.as_bytes()).expect("Unable to write data");
+            }
         }
+        println!("Reweighted to {} points in {:?}", alphas.len() * betas.len(), start.elapsed());
+        Ok(())
     }
+
+    dispatch!(simulate(&params, &alphas, &betas))
 }
 
-fn create_dir(path: &PathBuf) {
-    if Path::new(path).exists() == false {
-        create_dir_all(path).expect("Unable to create folder");
+pub fn check(overrides: &Overrides, configurations: usize, step: f64, tolerance: f64) -> Result<(), String> {
+    let params = overrides.apply(Parameters { alpha: 0.9, beta: 0.4, spread: 1.0, ..Default::default() })?;
+
+    fn compare<D: Dim>(params: &Parameters, configurations: usize, step: f64) -> Result<validation::Discrepancies, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let wf = WaveFunction { alpha: params.alpha, beta: params.beta, omega: params.omega, anisotropy: params.anisotropy, jastrow_on: params.jastrow };
+        validation::check_random::<D, _>(&wf, params.n, params.dim, configurations, params.spread, params.seed, step)
     }
-}
 
-fn create_file(filepath: &PathBuf) -> File {
-    match File::create(filepath) {
-        Ok(f) => f,
-        Err(why) => panic!("Unable to create {:?}: {}", filepath, why),
+    let discrepancies = dispatch_n!(compare(&params, configurations, step))?;
+    println!("Largest discrepancies over {} configurations with N = {} in {} dimensions:", configurations, params.n, params.dim);
+    println!("{}", discrepancies);
+
+    if discrepancies.max() > tolerance || discrepancies.max().is_nan() {
+        return Err(format!("Largest discrepancy {:.3e} is above the tolerance {:.3e}.", discrepancies.max(), tolerance));
     }
+    Ok(())
 }
@@ -1,41 +1,106 @@
-use crate::{Hamiltonian, Particle, Vector, WaveFunction, a};
+use crate::{Hamiltonian, Particle, TrialWaveFunction, Vector, WaveFunction, parameters::DriftPolicy};
 
-use nalgebra::base::MatrixSlice;
-use nalgebra::base::SMatrix;
-use nalgebra::base::SVector;
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim};
 use rand::distributions::{Distribution, Uniform};
-use rand::{prelude::random, thread_rng};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use rand_distr::Normal;
 
-pub struct System<const N: usize> {
+/// Time step of the Langevin equation the importance sampler moves particles with
+pub const TIME_STEP: f64 = 0.005;
+
+/// Step of the central differences of the numerical derivatives. The displaced wavefunctions are
+/// found from differences of logarithms of order N, which lose too many digits with a smaller step.
+const NUMERICAL_STEP: f64 = 1e-4;
+
+/// Settings of the periodic recomputation of the inverse Slater matrix. The Sherman-Morrison
+/// updates accumulate round-off with every accepted move, so every `interval` moves the inverse is
+/// computed from scratch and compared with the updated one.
+#[derive(Clone, Copy, Debug)]
+pub struct Refresh {
+    /// Number of accepted moves between every recomputation. 0 never recomputes the inverse
+    pub interval: usize,
+    /// Largest drift, relative to the largest element of the inverse, that is allowed
+    pub tolerance: f64,
+    /// What to do when the drift is too large, or the Slater matrix is nearly singular
+    pub policy: DriftPolicy,
+}
+
+impl Default for Refresh {
+    fn default() -> Self {
+        Refresh { interval: 1000, tolerance: 1e-6, policy: DriftPolicy::Warn }
+    }
+}
+
+impl Refresh {
+    /// Prints a warning or fails with `message`, depending on the drift policy
+    pub fn report(&self, message: String) -> Result<(), String> {
+        match self.policy {
+            DriftPolicy::Warn => {
+                eprintln!("Warning: {}", message);
+                Ok(())
+            }
+            DriftPolicy::Fail => Err(message),
+        }
+    }
+}
+
+/// A system of particles described by the trial wavefunction `W`. The state of the wavefunction,
+/// like its Slater matrices, is sized by `D`, which is `Const<N>` for a number of particles fixed
+/// at compile time, or `Dynamic` to choose it at runtime.
+pub struct System<D: Dim, W: TrialWaveFunction = WaveFunction>
+where
+    DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+{
     pub particles: Vec<Particle>,
     pub dim: usize,
-    pub wf: WaveFunction,
+    pub wf: W,
+    /// Hamiltonian the local energy is computed from. Defaults to the one the wavefunction is made
+    /// for, see `TrialWaveFunction::hamiltonian`
+    pub hamiltonian: Hamiltonian,
     pub interacting: bool,
     pub num_laplace: bool,
-    pub slater_inverse: SMatrix<f64, N, N>,
-    pub slater_ratio: f64,
-    v: SVector<f64, N>,
+    /// Whether the samples also include the derivatives of the local energy with regards to the
+    /// variational parameters, which the linear method needs. They are found by finite differences,
+    /// which recompute the state of the wavefunction twice per parameter for every sample.
+    pub energy_derivatives: bool,
+    /// What the wavefunction keeps of the current configuration, like the inverse Slater matrix
+    pub state: W::State<D>,
+    /// How often the state of the wavefunction is recomputed from scratch
+    pub refresh: Refresh,
+    /// Random number generator every random choice made for this system is drawn from
+    pub rng: StdRng,
+    moves_since_refresh: usize,
 }
 
-impl<const N: usize> System<N> {
-    /// Creates a new system with particles distributed randomly
+impl<D: Dim, W: TrialWaveFunction> System<D, W>
+where
+    DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+{
+    /// Creates a new system with particles distributed randomly. The same `seed` always gives the
+    /// same initial state, and the same sequence of Metropolis steps.
     pub fn new(
         n_particles: usize,
         dim: usize,
-        wf: WaveFunction,
+        wf: W,
         interacting: bool,
         num_laplace: bool,
         spread: f64,
+        seed: u64,
     ) -> Result<Self, String> {
-        let mut rng = thread_rng();
+        if let Some(size) = D::try_to_usize() {
+            if size != n_particles {
+                return Err(format!("A system of {} particles can't hold {} particles.", size, n_particles));
+            }
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
         let uniform = Uniform::new(0., 1.);
         let mut particles = vec![Particle::new(dim)?; n_particles];
-        let slater_inverse: SMatrix<f64, N, N>;
+        let state: W::State<D>;
 
-        // Keep initializing particles until we get an invertable Slater matrix
+        // Keep initializing particles until we get a configuration the wavefunction can start from,
+        // like one with an invertible Slater matrix
         loop {
-            for i in 0..particles.len() {
+            for particle in particles.iter_mut() {
                 // Make a new randomly placed particle
                 let new_particle = Particle::from_vector(match dim {
                     1 => Vector::D1(uniform.sample(&mut rng) - 0.5),
@@ -50,173 +115,139 @@ impl<const N: usize> System<N> {
                     ),
                 });
 
-                particles[i].position = new_particle.position.scale(spread);
+                particle.position = new_particle.position.scale(spread);
             }
 
-            // Slater matrix is not invertible when N = 2, so set it to a 0-matrix in that case.
-            if N == 2 {
-                slater_inverse = SMatrix::<f64, N, N>::repeat(0.);
+            if let Some(initial) = wf.initial_state(&particles)? {
+                state = initial;
                 break;
-            } else {
-                let slater_matrix: SMatrix<f64, N, N> = wf.slater_matrix(&particles)?;
-                match slater_matrix.try_inverse() {
-                    Some(inv) => {
-                        slater_inverse = inv;
-                        break;
-                    }
-                    _ => continue,
-                }
             }
         }
 
+        let hamiltonian = wf.hamiltonian();
+
         Ok(System {
             particles,
             dim,
             wf,
+            hamiltonian,
             interacting,
             num_laplace,
-            slater_inverse,
-            slater_ratio: 1.,
-            v: SVector::<f64, N>::repeat(0.),
+            energy_derivatives: false,
+            state,
+            refresh: Refresh::default(),
+            rng,
+            moves_since_refresh: 0,
         })
     }
 
     // NOTE: Storing the Laplacian here is messy, but it allows a much cleaner function signature.
     // WaveFunction and System are intimately tied together, and should've ideally been made as one
     // struct, but it is too late for that now.
-    /// Returns the Laplacian at this current state
+    /// Returns the Laplacian of the wavefunction divided by the wavefunction at this current state,
+    /// the sum over every particle of the Laplacian of the logarithm of the wavefunction and its
+    /// squared gradient
     pub fn laplace(&self) -> Result<f64, String> {
-        let mut result: f64 = 0.;
-        let n = self.particles.len();
-        let mut gradient_prod = 0.;
-
-        if self.num_laplace {
-            return Ok(self.wf.laplace_numerical::<N>(&self.particles)? / self.wf.evaluate::<N>(&self.particles)?);
-        }
-
-        if n == 2 && !self.wf.jastrow_on {
-            let r1 = self.particles[0].squared_sum();
-            let r2 = self.particles[1].squared_sum();
-            let alpha_omega = self.wf.alpha * self.wf.omega;
-            let laplace = alpha_omega.powi(2) * (r1 + r2) - 4. * alpha_omega;
-            return Ok(laplace);
+        let mut laplace = 0.;
+        for i in 0..self.particles.len() {
+            let (gradient, laplace_log) = self.log_psi_derivatives(i)?;
+            laplace += laplace_log + gradient.inner(gradient)?;
         }
+        Ok(laplace)
+    }
 
-        for i in 0..n {
-            for j in 0..n {
-                let nx = crate::QUANTUM_NUMBERS[j].0;
-                let ny = crate::QUANTUM_NUMBERS[j].1;
-                result += if n == 2 {
-                    let laplace_jastrow = if j != i {
-                        let distance = self.particles[i].distance_to(&self.particles[j])?;
-                        let fraction = a(i, j, n) / (1. + self.wf.beta * distance).powi(2);
-                        fraction / distance - 2. * self.wf.beta * fraction / (1. + self.wf.beta * distance)
-                    } else { 0. };
-
-                    self.wf.laplace_spf(&self.particles[i], nx, ny)? + laplace_jastrow
-                } else {
-                    // This whole mess is from the Jastrow factor (N^3, jesus christ...)
-                    let laplace_jastrow = if self.wf.jastrow_on && j != i {
-                        let distance = self.particles[i].distance_to(&self.particles[j])?;
-                        let fraction = a(i, j, n) / (1. + self.wf.beta * distance).powi(2);
-                        let mut result = fraction / distance - 2. * self.wf.beta * fraction / (1. + self.wf.beta * distance);
-                        let rij = self.particles[i].position + self.particles[j].position.scale(-1.);
-                        for k in 0..n {
-                            if k == i { continue }
-                            let rik = self.particles[i].position + self.particles[k].position.scale(-1.);
-                            let distance2 = self.particles[i].distance_to(&self.particles[k])?;
-                            let fraction2 = a(i, k, n) / (1. + self.wf.beta * distance2).powi(2);
-                            result += rik.inner(rij)? / (distance * distance2) * fraction * fraction2;
-                        }
-                        result
-                    } else { 0. };
-
-                    self.wf.laplace_spf(&self.particles[i], nx, ny)? + laplace_jastrow
-                    // self.wf.laplace_spf(&self.particles[i], nx, ny)? * self.slater_inverse[(j, i)] + laplace_jastrow
-                };
-            }
-            gradient_prod += self.wf.gradient_slater(i, &self.particles, &self.slater_inverse)?
-                                    .inner(self.wf.gradient_jastrow(i, &self.particles)?)?;
+    /// Returns the gradient and the Laplacian of the logarithm of the wavefunction with regards to
+    /// particle `i`, from finite differences if `num_laplace` is set and analytically otherwise.
+    pub fn log_psi_derivatives(&self, i: usize) -> Result<(Vector, f64), String> {
+        if self.num_laplace {
+            return self.wf.log_psi_derivatives_numerical(i, &self.particles, NUMERICAL_STEP);
         }
 
-        Ok(result + 2. * gradient_prod)
+        Ok((
+            self.wf.grad_log_psi(i, &self.particles, &self.state)?,
+            self.wf.laplacian_log_psi(i, &self.particles, &self.state)?,
+        ))
     }
 
-    pub fn next_slater_inverse(
-        &mut self,
-        new_particles: &Vec<Particle>,
-        p: usize,
-    ) -> Result<SMatrix<f64, N, N>, String> {
-        // Find v_p
-        for i in 0..N {
-            let ny = crate::QUANTUM_NUMBERS[i].1;
-            let nx = crate::QUANTUM_NUMBERS[i].0;
-            // let spf_old = self.wf.spf(&self.particles[p], nx, ny)?;
-            let spf_new = self.wf.spf(&new_particles[p], nx, ny)?; 
-            self.v[i] = spf_new // - spf_old
-        }
+    /// Places the particles at `particles` and computes the state of the wavefunction there from
+    /// scratch
+    pub fn set_particles(&mut self, particles: Vec<Particle>) -> Result<(), String> {
+        self.state = self.wf.initial_state(&particles)?
+            .ok_or_else(|| "The wavefunction can't be evaluated at the given configuration.".to_owned())?;
+        self.particles = particles;
+        self.moves_since_refresh = 0;
+        Ok(())
+    }
 
-        let mut new_inverse: SMatrix<f64, N, N> = SMatrix::repeat(0.);
-        // let identity: SMatrix<f64, N, N> = SMatrix::identity();
-        let factor = 1. / self.slater_ratio;
-        // NOTE: Double for-loop, so the complexity is O(n^2), contary to what we mention in Method...
-        for i in 0..N {
-            // i'th column
-            let mut s = 0.;
-            for l in 0..N {
-                s += self.v[l] * self.slater_inverse[(l, i)]
-            }
-            for j in 0..N {
-                // j'th row
-                new_inverse[(j, i)] = if i == p {
-                    factor * self.slater_inverse[(j, p)]
-                } else {
-                    self.slater_inverse[(j, i)] - s * factor * self.slater_inverse[(j, p)]
-                }
-                /* new_inverse[(i, j)] = (identity[(i, j)]
-                    - self.slater_inverse[(i, p)] * self.v[i] / self.slater_ratio)
-                    * self.slater_inverse[(i, j)]; */
+    /// Returns the derivative of the local energy with regards to each variational parameter, from
+    /// central differences of the analytic Laplacian. Only the kinetic energy depends on the
+    /// parameters.
+    pub fn local_energy_derivatives(&mut self) -> Result<Vec<f64>, String> {
+        let parameters = self.wf.parameters();
+        let particles = &self.particles;
+        let laplace = |wf: &mut W, k: usize, shift: f64| -> Result<f64, String> {
+            let mut shifted = parameters.clone();
+            shifted[k] += shift;
+            wf.set_parameters(&shifted)?;
+            let state: W::State<D> = wf.initial_state(particles)?
+                .ok_or_else(|| "The wavefunction is singular with the shifted parameters.".to_owned())?;
+            let mut laplace = 0.;
+            for i in 0..particles.len() {
+                let gradient = wf.grad_log_psi(i, particles, &state)?;
+                laplace += wf.laplacian_log_psi(i, particles, &state)? + gradient.inner(gradient)?;
             }
-        }
+            Ok(laplace)
+        };
 
-        Ok(new_inverse)
+        let wf = &mut self.wf;
+        let derivatives = (0..parameters.len())
+            .map(|k| {
+                let difference = laplace(wf, k, NUMERICAL_STEP)? - laplace(wf, k, -NUMERICAL_STEP)?;
+                Ok(-0.5 * difference / (2. * NUMERICAL_STEP))
+            })
+            .collect::<Result<Vec<f64>, String>>();
+        // The parameters are restored even if a derivative failed
+        self.wf.set_parameters(&parameters)?;
+        derivatives
     }
 
-    /// Returns the new Slater ratio
-    pub fn next_slater_ratio(&self, p: usize, new_inverse: &SMatrix<f64, N, N>) -> f64 {
-        let mut result = 0.;
-        for i in 0..N {
-            result += self.v[i] * new_inverse[(i, p)];
-        }
-        1. + result
+    /// Returns the logarithm of the absolute value of the ratio between the wavefunction with
+    /// particle `p` moved to its position in `new_particles` and the current one.
+    pub fn log_ratio(&mut self, p: usize, new_particles: &[Particle]) -> Result<f64, String> {
+        self.wf.log_ratio(p, &self.particles, new_particles, &mut self.state)
     }
 
-    /// Returns the new Jastrow ratio
-    pub fn next_jastrow_ratio(&self, p: usize, new_particles: &Vec<Particle>) -> f64 {
-        let n = self.particles.len();
-        let mut result = 0.;
-        for i in 0..n {
-            if i == p { continue }
-            // Can safely unwrap these, as we know the particles share the same dimensionality
-            let old_distance = self.particles[p].distance_to(&self.particles[0]).unwrap();
-            let new_distance = new_particles[p].distance_to(&new_particles[0]).unwrap();
-            result += a(i, p, n) * new_distance / (1. + self.wf.beta * new_distance)
-                    - a(i, p, n) * old_distance / (1. + self.wf.beta * old_distance)
+    /// Moves particle `p` to its position in `new_particles`, which must be the move last passed to
+    /// `log_ratio`, and updates the state of the wavefunction. Every `refresh.interval` moves the
+    /// state is recomputed from scratch, see `refresh_state`.
+    pub fn accept_move(&mut self, p: usize, new_particles: Vec<Particle>) -> Result<(), String> {
+        self.wf.update_state(p, &new_particles, &mut self.state)?;
+        self.particles = new_particles;
+        self.moves_since_refresh += 1;
+        if self.refresh.interval > 0 && self.moves_since_refresh >= self.refresh.interval {
+            self.refresh_state()?;
         }
-        result.exp()
+        Ok(())
+    }
+
+    /// Recomputes the state of the wavefunction from scratch, and returns how far the updated state
+    /// had drifted from it. Problems are handled according to `refresh.policy`.
+    pub fn refresh_state(&mut self) -> Result<f64, String> {
+        self.moves_since_refresh = 0;
+        self.wf.refresh_state(&self.particles, &mut self.state, &self.refresh)
     }
 
     /// Change a random particle's position by a random value
-    pub fn random_particle_change(&self, step_size: f64) -> (Vec<Particle>, usize) {
+    pub fn random_particle_change(&mut self, step_size: f64) -> (Vec<Particle>, usize) {
         let mut new_particles = self.particles.clone();
-        let i = random::<usize>() % self.particles.len();
+        let i = self.rng.gen_range(0..self.particles.len());
         let add = match new_particles[i].position {
-            Vector::D1(_) => Vector::D1(random::<f64>() - 0.5),
-            Vector::D2(_, _) => Vector::D2(random::<f64>() - 0.5, random::<f64>() - 0.5),
+            Vector::D1(_) => Vector::D1(self.rng.gen::<f64>() - 0.5),
+            Vector::D2(_, _) => Vector::D2(self.rng.gen::<f64>() - 0.5, self.rng.gen::<f64>() - 0.5),
             Vector::D3(_, _, _) => Vector::D3(
-                random::<f64>() - 0.5,
-                random::<f64>() - 0.5,
-                random::<f64>() - 0.5,
+                self.rng.gen::<f64>() - 0.5,
+                self.rng.gen::<f64>() - 0.5,
+                self.rng.gen::<f64>() - 0.5,
             ),
         };
         new_particles[i].position += add.scale(step_size);
@@ -225,49 +256,193 @@ impl<const N: usize> System<N> {
 
     /// Takes in a step size and returns the next particle state of the system.
     pub fn quantum_force_particle_change(&mut self) -> Result<(Vec<Particle>, usize), String> {
-        let mut rng = thread_rng();
         let normal = Normal::new(0., 1.).unwrap();
 
-        // 0.005 is hard-coded solution for delta t in Langevin equation
-        let qf_step_size: f64 = 0.005;
-
         // Picks one random particle to do the change for
-        let i = random::<usize>() % self.particles.len();
-        let nx = crate::QUANTUM_NUMBERS[i].0;
-        let ny = crate::QUANTUM_NUMBERS[i].1;
-
-        self.particles[i].qforce = if self.interacting {
-            self.wf.quantum_force(i, &self.particles, &self.slater_inverse)?
-        } else {
-            self.wf.quantum_force_non_interacting(&self.particles[i], nx, ny)?
-        };
+        let i = self.rng.gen_range(0..self.particles.len());
+        self.particles[i].qforce = self.wf.quantum_force(i, &self.particles, &self.state)?;
 
         // Clones the last particle state of the system
         let mut new_particles = self.particles.clone();
 
         // Do Langevin equation (NOTE: Consider making a function for random vectors to avoid this mess)
         new_particles[i].position = new_particles[i].position
-            + self.particles[i].qforce.scale(0.5 * qf_step_size)
+            + self.particles[i].qforce.scale(0.5 * TIME_STEP)
             + (match new_particles[i].position {
-                Vector::D1(_) => Vector::D1(normal.sample(&mut rng)),
-                Vector::D2(_, _) => Vector::D2(normal.sample(&mut rng), normal.sample(&mut rng)),
+                Vector::D1(_) => Vector::D1(normal.sample(&mut self.rng)),
+                Vector::D2(_, _) => Vector::D2(normal.sample(&mut self.rng), normal.sample(&mut self.rng)),
                 Vector::D3(_, _, _) => Vector::D3(
-                    normal.sample(&mut rng),
-                    normal.sample(&mut rng),
-                    normal.sample(&mut rng),
+                    normal.sample(&mut self.rng),
+                    normal.sample(&mut self.rng),
+                    normal.sample(&mut self.rng),
                 ),
             })
-            .scale(qf_step_size.sqrt());
+            .scale(TIME_STEP.sqrt());
 
-        let new_inverse = self.next_slater_inverse(&new_particles, i)?;
+        self.log_ratio(i, &new_particles)?;
+        let mut new_state = self.state.clone();
+        self.wf.update_state(i, &new_particles, &mut new_state)?;
 
         // Calculate quantum force of new state
-        new_particles[i].qforce = if self.interacting {
-            self.wf.quantum_force(i, &new_particles, &new_inverse)?
-        } else {
-            self.wf.quantum_force_non_interacting(&new_particles[i], nx, ny)?
-        };
+        new_particles[i].qforce = self.wf.quantum_force(i, &new_particles, &new_state)?;
 
         Ok((new_particles, i))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Const, Dynamic};
+
+    fn system(seed: u64) -> System<Const<6>> {
+        let wf = WaveFunction { alpha: 1., beta: 0.5, omega: 1., anisotropy: [1.; 3], jastrow_on: true };
+        System::new(6, 2, wf, true, false, 1., seed).unwrap()
+    }
+
+    #[test]
+    fn test_spin_factorization() {
+        let sys = system(1);
+        let det = sys.wf.slater_determinant(&sys.particles).unwrap();
+        assert!(det.abs() > 1e-10);
+
+        // Swapping two particles of equal spin flips the sign, while particles of opposite spin
+        // belong to different determinants and can't be swapped
+        let mut swapped = sys.particles.clone();
+        swapped.swap(0, 2);
+        assert!((sys.wf.slater_determinant(&swapped).unwrap() + det).abs() < 1e-12);
+
+        let inverse = sys.wf.slater_inverse::<Const<6>>(&sys.particles).unwrap().unwrap();
+        assert_eq!(inverse.fixed_slice::<3, 3>(0, 3).abs().max(), 0.);
+        assert_eq!(inverse.fixed_slice::<3, 3>(3, 0).abs().max(), 0.);
+    }
+
+    #[test]
+    fn test_inverse_update() {
+        let mut sys = system(2);
+        for _ in 0..50 {
+            let (new_particles, p) = sys.random_particle_change(0.5);
+            let log_ratio = sys.log_ratio(p, &new_particles).unwrap();
+            let want = sys.wf.log_psi(&new_particles).unwrap() - sys.wf.log_psi(&sys.particles).unwrap();
+            assert!((log_ratio - want).abs() < 1e-8);
+
+            sys.accept_move(p, new_particles).unwrap();
+            let want = sys.wf.slater_inverse::<Const<6>>(&sys.particles).unwrap().unwrap();
+            assert!((sys.state.inverse - want).abs().max() < 1e-8 * want.abs().max());
+        }
+    }
+
+    #[test]
+    fn test_refresh() {
+        let mut sys = system(3);
+        sys.refresh = Refresh { interval: 10, tolerance: 1e-8, policy: DriftPolicy::Fail };
+        for _ in 0..100 {
+            let (new_particles, p) = sys.random_particle_change(0.5);
+            sys.log_ratio(p, &new_particles).unwrap();
+            sys.accept_move(p, new_particles).unwrap();
+        }
+        assert_eq!(sys.moves_since_refresh, 0);
+
+        // A corrupted inverse is replaced, and reported according to the policy
+        let want = sys.state.inverse;
+        sys.state.inverse *= 1.001;
+        assert!(sys.refresh_state().is_err());
+        assert_eq!(sys.state.inverse, want);
+        sys.refresh.policy = DriftPolicy::Warn;
+        sys.state.inverse *= 1.001;
+        let drift = sys.refresh_state().unwrap();
+        assert!((drift - 1e-3).abs() < 1e-6);
+        assert_eq!(sys.state.inverse, want);
+    }
+
+    #[test]
+    fn test_refresh_near_singular() {
+        let mut sys = system(4);
+        sys.refresh.policy = DriftPolicy::Fail;
+        assert!(sys.refresh_state().is_ok());
+
+        // Two particles of the same spin almost on top of each other make two rows nearly equal
+        let mut particles = sys.particles.clone();
+        particles[1].position = particles[0].position + Vector::D2(1e-14, 0.);
+        sys.particles = particles;
+        assert!(sys.refresh_state().is_err());
+    }
+
+    #[test]
+    fn test_dynamic_size() {
+        let wf = WaveFunction { alpha: 0.9, beta: 0.4, omega: 1., anisotropy: [1.; 3], jastrow_on: true };
+        let mut fixed = System::<Const<6>>::new(6, 2, wf.clone(), true, false, 1., 7).unwrap();
+        let mut dynamic = System::<Dynamic>::new(6, 2, wf.clone(), true, false, 1., 7).unwrap();
+
+        // The same seed gives the same walk whichever way the matrices are sized
+        for _ in 0..20 {
+            let (new_particles, p) = fixed.random_particle_change(0.5);
+            fixed.log_ratio(p, &new_particles).unwrap();
+            fixed.accept_move(p, new_particles).unwrap();
+
+            let (new_particles, p) = dynamic.random_particle_change(0.5);
+            dynamic.log_ratio(p, &new_particles).unwrap();
+            dynamic.accept_move(p, new_particles).unwrap();
+        }
+        assert_eq!(fixed.state.inverse.as_slice(), dynamic.state.inverse.as_slice());
+        assert_eq!(fixed.hamiltonian.energy(&fixed).unwrap(), dynamic.hamiltonian.energy(&dynamic).unwrap());
+
+        // Particle counts without a static size are only possible with a dynamic one
+        assert!(System::<Const<6>>::new(12, 2, wf.clone(), true, false, 1., 7).is_err());
+        assert!(System::<Dynamic>::new(72, 2, wf, true, false, 1., 7).is_ok());
+    }
+
+    #[test]
+    fn test_local_energy_derivatives() {
+        // For a product of Gaussians the local energy is alpha d N / 2 + (1 - alpha^2) r^2 / 2, summed
+        // over the particles
+        let wf = crate::GaussianProduct { alpha: 0.7, omega: 1., anisotropy: [1.; 3] };
+        let mut sys = System::<Dynamic, _>::new(3, 2, wf, false, false, 1., 8).unwrap();
+        let r2: f64 = sys.particles.iter().map(|p| p.position.inner(p.position).unwrap()).sum();
+        let derivatives = sys.local_energy_derivatives().unwrap();
+        assert!((derivatives[0] - (3. - 0.7 * r2)).abs() < 1e-6);
+        assert_eq!(sys.wf.alpha, 0.7);
+    }
+
+    /// Laplacian divided by the wavefunction from central differences of the wavefunction
+    fn finite_difference_laplace<D: Dim>(sys: &System<D>) -> f64
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let h = 1e-4;
+        let psi = sys.wf.evaluate_log(&sys.particles).unwrap();
+        let mut particles = sys.particles.clone();
+        let mut laplace = 0.;
+        for i in 0..sys.particles.len() {
+            for d in 0..sys.dim {
+                particles[i].bump_at_dim(d, h);
+                let plus = sys.wf.ratio_to(&particles, psi).unwrap();
+                particles[i].bump_at_dim(d, -2. * h);
+                let minus = sys.wf.ratio_to(&particles, psi).unwrap();
+                particles[i].bump_at_dim(d, h);
+                laplace += (plus - 2. + minus) / (h * h);
+            }
+        }
+        laplace
+    }
+
+    #[test]
+    fn test_analytic_laplace() {
+        for &jastrow_on in [false, true].iter() {
+            let wf = WaveFunction { alpha: 0.9, beta: 0.4, omega: 1., anisotropy: [1., 1.2, 0.8], jastrow_on };
+            let two = System::<Const<2>>::new(2, 2, wf.clone(), true, false, 1., 3).unwrap();
+            let six = System::<Const<6>>::new(6, 2, wf.clone(), true, false, 1., 4).unwrap();
+            let eight = System::<Const<8>>::new(8, 3, wf.clone(), true, false, 1., 5).unwrap();
+            let six_1d = System::<Const<6>>::new(6, 1, wf.clone(), true, false, 1., 6).unwrap();
+
+            for (got, want) in [
+                (two.laplace().unwrap(), finite_difference_laplace(&two)),
+                (six.laplace().unwrap(), finite_difference_laplace(&six)),
+                (eight.laplace().unwrap(), finite_difference_laplace(&eight)),
+                (six_1d.laplace().unwrap(), finite_difference_laplace(&six_1d)),
+            ].iter() {
+                assert!((got - want).abs() < 1e-4 * (1. + want.abs()), "{} != {}", got, want);
+            }
+        }
+    }
+}
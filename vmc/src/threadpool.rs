@@ -1,3 +1,5 @@
+use crate::utils::stream_seed;
+use std::cell::Cell;
 use std::sync::mpsc::{channel, Sender};
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -5,10 +7,23 @@ use std::sync::Mutex;
 pub struct ThreadPool {
     handles: Vec<std::thread::JoinHandle<()>>,
     sender: Sender<Box<dyn Fn() + Send>>,
+    seed: u64,
+    jobs: Cell<u64>,
 }
 
 impl ThreadPool {
-    pub fn new(num_threads: u8) -> Self {
+    /// Makes a pool whose random number streams are derived from a random master seed
+    pub fn new(num_threads: u8) -> Result<Self, String> {
+        Self::with_seed(num_threads, rand::random())
+    }
+
+    /// Makes a pool where every job executed with `execute_seeded` gets its own random number
+    /// stream, derived from `seed` and the order the jobs are given in. A pool without threads
+    /// would never run its jobs, so it is an error.
+    pub fn with_seed(num_threads: u8, seed: u64) -> Result<Self, String> {
+        if num_threads == 0 {
+            return Err("A thread pool needs at least one thread.".to_owned());
+        }
         let (sender, receiver) = channel::<Box<dyn Fn() + Send>>(); //Spawn a sender and receiver in order to pass instructions to threads, these has to be boxed, and cointain the trait Send in order to be able to send, dyn Fn is because the function to send must be dynamic.
         let reciever = Arc::new(Mutex::new(receiver)); //Make a mutex (which gives explicit access for muting, maybe not needed?) and then in an Arc, letting all threads access it.
         let mut handles: Vec<std::thread::JoinHandle<()>> = vec![]; //Init vector of thread handles, also called workers.
@@ -23,13 +38,12 @@ impl ThreadPool {
                     Err(_) => break,
                 };
                 //println!("Start {:?}", std::thread::current().id());
-                work(); //Doing the work
-                break; //Kill thread when done
+                work(); //Doing the work, then waiting for the next job until join_all closes the channel
             });
             handles.push(handle); //This is ofc done before work() is completed, so the handle is added to vec of handles!
         }
 
-        Self { handles, sender } //Returning self in order to grab it from execute() and join_all()
+        Ok(Self { handles, sender, seed, jobs: Cell::new(0) }) //Returning self in order to grab it from execute() and join_all()
     }
 
     pub fn execute<T: Fn() + Send + 'static>(&self, work: T) {
@@ -37,7 +51,17 @@ impl ThreadPool {
         self.sender.send(Box::new(work)).unwrap(); //Sends workload to the worker
     }
 
+    /// Same as `execute`, but passes the seed of an independent random number stream to the work
+    pub fn execute_seeded<T: Fn(u64) + Send + 'static>(&self, work: T) {
+        let seed = stream_seed(self.seed, self.jobs.get());
+        self.jobs.set(self.jobs.get() + 1);
+        self.execute(move || work(seed));
+    }
+
+    /// Waits for every job to finish. Closing the channel tells the threads that no more jobs are
+    /// coming, so they stop once it is empty.
     pub fn join_all(self) {
+        drop(self.sender);
         for handle in self.handles {
             let _ = handle.join();
         }
@@ -49,10 +73,41 @@ mod tests {
     use super::*; //pulls ThreadPool into scope
     #[test]
     fn test_threadpool() {
-        let pool = ThreadPool::new(3);
+        let pool = ThreadPool::new(3).unwrap();
         pool.execute(|| println!("Hello from {:?}", std::thread::current().id()));
         pool.execute(|| println!("Hello from {:?}", std::thread::current().id()));
         pool.execute(|| println!("Hello from {:?}", std::thread::current().id()));
         std::thread::sleep(std::time::Duration::from_secs(2));
     }
+
+    #[test]
+    fn test_execute_seeded() {
+        let seeds = Arc::new(Mutex::new(vec![]));
+        let pool = ThreadPool::with_seed(3, 42).unwrap();
+        for _ in 0..3 {
+            let seeds = seeds.clone();
+            pool.execute_seeded(move |seed| seeds.lock().unwrap().push(seed));
+        }
+        pool.join_all();
+
+        let mut got = seeds.lock().unwrap().clone();
+        got.sort();
+        let mut want: Vec<u64> = (0..3).map(|i| stream_seed(42, i)).collect();
+        want.sort();
+        assert_eq!(got, want);
+        assert!(want[0] != want[1] && want[1] != want[2]);
+    }
+
+    #[test]
+    fn test_more_jobs_than_threads() {
+        let done = Arc::new(Mutex::new(0));
+        assert!(ThreadPool::new(0).is_err());
+        let pool = ThreadPool::new(2).unwrap();
+        for _ in 0..10 {
+            let done = done.clone();
+            pool.execute(move || *done.lock().unwrap() += 1);
+        }
+        pool.join_all();
+        assert_eq!(*done.lock().unwrap(), 10);
+    }
 }
@@ -0,0 +1,375 @@
+use crate::{interaction::Coulomb, potential::{CoulombCenter, HarmonicOscillator}, system::Refresh, Hamiltonian, Particle, Variational, Vector};
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim};
+
+/// A trial wavefunction that a `System` can be sampled with. Besides evaluating the wavefunction,
+/// it gives the ratios, gradients and Laplacians the Metropolis algorithms and the local energy
+/// need, and the derivatives with regards to its variational parameters.
+///
+/// Anything a wavefunction wants to keep of the current configuration between moves, like the
+/// inverse of a Slater matrix, is its `State`. `D` sizes the state of wavefunctions with one
+/// matrix row per particle; wavefunctions without state use `()`.
+pub trait TrialWaveFunction: Variational {
+    type State<D: Dim>: Clone
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>;
+
+    /// Returns the sign of the wavefunction and the logarithm of its absolute value, which unlike
+    /// the wavefunction itself neither overflows nor underflows for large systems.
+    fn evaluate_log(&self, particles: &[Particle]) -> Result<(f64, f64), String>;
+
+    /// Returns the Hamiltonian of the system the wavefunction is made for, which is what a new
+    /// `System` computes the local energy from
+    fn hamiltonian(&self) -> Hamiltonian;
+
+    /// Returns the state at `particles`, or `None` if it can't be computed there, which makes
+    /// `System::new` try another configuration.
+    fn initial_state<D: Dim>(&self, particles: &[Particle]) -> Result<Option<Self::State<D>>, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>;
+
+    /// Returns the logarithm of the absolute value of the ratio between the wavefunction with
+    /// particle `p` moved to its position in `new_particles` and the current one. `state` may keep
+    /// what it needs for `update_state` to accept the move.
+    fn log_ratio<D: Dim>(
+        &self,
+        p: usize,
+        particles: &[Particle],
+        new_particles: &[Particle],
+        state: &mut Self::State<D>,
+    ) -> Result<f64, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>;
+
+    /// Updates `state` to the move of particle `p` last passed to `log_ratio`
+    fn update_state<D: Dim>(&self, _p: usize, _new_particles: &[Particle], _state: &mut Self::State<D>) -> Result<(), String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        Ok(())
+    }
+
+    /// Recomputes `state` from scratch, undoing the round-off of the updates, and returns how far
+    /// the updated state had drifted. Problems are reported according to `refresh.policy`.
+    fn refresh_state<D: Dim>(&self, _particles: &[Particle], _state: &mut Self::State<D>, _refresh: &Refresh) -> Result<f64, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        Ok(0.)
+    }
+
+    /// Returns the gradient of the logarithm of the wavefunction with regards to particle `p`,
+    /// which is the gradient of the wavefunction divided by the wavefunction
+    fn grad_log_psi<D: Dim>(&self, p: usize, particles: &[Particle], state: &Self::State<D>) -> Result<Vector, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>;
+
+    /// Returns the Laplacian of the logarithm of the wavefunction with regards to particle `p`.
+    /// The Laplacian of the wavefunction divided by the wavefunction is this plus the squared
+    /// norm of `grad_log_psi`.
+    fn laplacian_log_psi<D: Dim>(&self, p: usize, particles: &[Particle], state: &Self::State<D>) -> Result<f64, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>;
+
+    /// Returns O_k = d ln(psi) / d theta_k, the derivative of the logarithm of the wavefunction
+    /// with regards to each parameter, at `particles`
+    fn log_derivatives<D: Dim>(&self, particles: &[Particle], state: &Self::State<D>) -> Result<Vec<f64>, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>;
+
+    /// Returns the (unnormalized) value of the wavefunction
+    fn evaluate(&self, particles: &[Particle]) -> Result<f64, String> {
+        let (sign, log) = self.evaluate_log(particles)?;
+        Ok(sign * log.exp())
+    }
+
+    /// Returns the logarithm of the absolute value of the wavefunction
+    fn log_psi(&self, particles: &[Particle]) -> Result<f64, String> {
+        Ok(self.evaluate_log(particles)?.1)
+    }
+
+    /// Returns the wavefunction at `particles` divided by a wavefunction of the given sign and
+    /// logarithm, as returned by `evaluate_log`
+    fn ratio_to(&self, particles: &[Particle], (sign, log): (f64, f64)) -> Result<f64, String> {
+        let (new_sign, new_log) = self.evaluate_log(particles)?;
+        Ok(new_sign * sign * (new_log - log).exp())
+    }
+
+    /// Returns the quantum force of particle `p`, twice the gradient of the logarithm of the
+    /// wavefunction
+    fn quantum_force<D: Dim>(&self, p: usize, particles: &[Particle], state: &Self::State<D>) -> Result<Vector, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        Ok(self.grad_log_psi(p, particles, state)?.scale(2.))
+    }
+
+    /// Returns the gradient and the Laplacian of the logarithm of the wavefunction with regards to
+    /// particle `p`, from central differences with step `h`. The differences are taken of the
+    /// displaced wavefunction relative to the undisplaced one, see `ratio_to`, which is smooth even
+    /// close to a node where the logarithm itself is not.
+    fn log_psi_derivatives_numerical(&self, p: usize, particles: &[Particle], h: f64) -> Result<(Vector, f64), String> {
+        let mut particles = particles.to_vec();
+        let psi = self.evaluate_log(&particles)?;
+
+        let mut gradient = vec![0.; particles[p].dim];
+        let mut laplace = 0.;
+        for (dim, gradient) in gradient.iter_mut().enumerate() {
+            particles[p].bump_at_dim(dim, h); // Initial position +h
+            let ratio_plus = self.ratio_to(&particles, psi)?;
+
+            particles[p].bump_at_dim(dim, -2. * h); // Initial position -h
+            let ratio_minus = self.ratio_to(&particles, psi)?;
+
+            particles[p].bump_at_dim(dim, h); // Reset back to initial position
+
+            *gradient = (ratio_plus - ratio_minus) / (2. * h);
+            laplace += (ratio_plus - 2. + ratio_minus) / h.powi(2) - gradient.powi(2);
+        }
+
+        Ok((Vector::from_slice(&gradient)?, laplace))
+    }
+}
+
+/// Product of the same Gaussian for every particle, exp(-alpha omega_d x_d^2 / 2) along each axis.
+/// It is symmetric, so it describes bosons in the ground state of a harmonic oscillator trap, which
+/// it is exactly for alpha = 1 without interactions.
+#[derive(Clone, Debug)]
+pub struct GaussianProduct {
+    pub alpha: f64,
+    pub omega: f64,
+    /// Trap frequency along each axis relative to `omega`
+    pub anisotropy: [f64; 3],
+}
+
+impl GaussianProduct {
+    /// Returns alpha times the trap frequency along axis `d`
+    fn k(&self, d: usize) -> f64 {
+        self.alpha * self.omega * self.anisotropy[d]
+    }
+
+    /// Returns the logarithm of the Gaussian of a single particle
+    fn log_single(&self, particle: &Particle) -> f64 {
+        (0..particle.dim).map(|d| -0.5 * self.k(d) * particle.position.get(d).unwrap().powi(2)).sum()
+    }
+}
+
+impl Variational for GaussianProduct {
+    fn parameter_names(&self) -> Vec<&'static str> {
+        vec!["alpha"]
+    }
+
+    fn parameters(&self) -> Vec<f64> {
+        vec![self.alpha]
+    }
+
+    fn set_parameters(&mut self, parameters: &[f64]) -> Result<(), String> {
+        match parameters {
+            &[alpha] => self.alpha = alpha,
+            _ => return Err(format!("Expected the parameters {:?}, got {} values.", self.parameter_names(), parameters.len())),
+        }
+        Ok(())
+    }
+}
+
+impl TrialWaveFunction for GaussianProduct {
+    type State<D: Dim> = ()
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>;
+
+    fn evaluate_log(&self, particles: &[Particle]) -> Result<(f64, f64), String> {
+        Ok((1., particles.iter().map(|particle| self.log_single(particle)).sum()))
+    }
+
+    fn hamiltonian(&self) -> Hamiltonian {
+        Hamiltonian::new(HarmonicOscillator { omega: self.omega, anisotropy: self.anisotropy }, Coulomb)
+    }
+
+    fn initial_state<D: Dim>(&self, _: &[Particle]) -> Result<Option<()>, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        Ok(Some(()))
+    }
+
+    fn log_ratio<D: Dim>(&self, p: usize, particles: &[Particle], new_particles: &[Particle], _: &mut ()) -> Result<f64, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        Ok(self.log_single(&new_particles[p]) - self.log_single(&particles[p]))
+    }
+
+    fn grad_log_psi<D: Dim>(&self, p: usize, particles: &[Particle], _: &()) -> Result<Vector, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let position = particles[p].position;
+        let gradient: Vec<f64> = (0..particles[p].dim).map(|d| -self.k(d) * position.get(d).unwrap()).collect();
+        Vector::from_slice(&gradient)
+    }
+
+    fn laplacian_log_psi<D: Dim>(&self, p: usize, particles: &[Particle], _: &()) -> Result<f64, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        Ok((0..particles[p].dim).map(|d| -self.k(d)).sum())
+    }
+
+    fn log_derivatives<D: Dim>(&self, particles: &[Particle], _: &()) -> Result<Vec<f64>, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        Ok(vec![self.log_psi(particles)? / self.alpha])
+    }
+}
+
+/// Product of the same hydrogen-like orbital exp(-alpha r) for every particle, with r the distance
+/// to a point charge `charge` at the origin. In three dimensions it is the ground state of each
+/// particle for alpha = charge when they do not interact, and in two for alpha = 2 charge. The
+/// orbital has a cusp at the origin, where its gradient and Laplacian are undefined, so they are
+/// errors there rather than NaN.
+#[derive(Clone, Debug)]
+pub struct HydrogenOrbital {
+    pub alpha: f64,
+    pub charge: f64,
+}
+
+impl HydrogenOrbital {
+    fn distance(particle: &Particle) -> f64 {
+        particle.squared_sum().sqrt()
+    }
+
+    /// Returns the distance of a particle that is not at the cusp of the orbital
+    fn distance_off_cusp(particle: &Particle) -> Result<f64, String> {
+        match Self::distance(particle) {
+            r if r > 0. => Ok(r),
+            _ => Err("The derivatives of the hydrogen orbital are undefined at the origin.".to_owned()),
+        }
+    }
+}
+
+impl Variational for HydrogenOrbital {
+    fn parameter_names(&self) -> Vec<&'static str> {
+        vec!["alpha"]
+    }
+
+    fn parameters(&self) -> Vec<f64> {
+        vec![self.alpha]
+    }
+
+    fn set_parameters(&mut self, parameters: &[f64]) -> Result<(), String> {
+        match parameters {
+            &[alpha] => self.alpha = alpha,
+            _ => return Err(format!("Expected the parameters {:?}, got {} values.", self.parameter_names(), parameters.len())),
+        }
+        Ok(())
+    }
+}
+
+impl TrialWaveFunction for HydrogenOrbital {
+    type State<D: Dim> = ()
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>;
+
+    fn evaluate_log(&self, particles: &[Particle]) -> Result<(f64, f64), String> {
+        Ok((1., particles.iter().map(|particle| -self.alpha * Self::distance(particle)).sum()))
+    }
+
+    fn hamiltonian(&self) -> Hamiltonian {
+        Hamiltonian::new(CoulombCenter { charge: self.charge }, Coulomb)
+    }
+
+    fn initial_state<D: Dim>(&self, _: &[Particle]) -> Result<Option<()>, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        Ok(Some(()))
+    }
+
+    fn log_ratio<D: Dim>(&self, p: usize, particles: &[Particle], new_particles: &[Particle], _: &mut ()) -> Result<f64, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        Ok(-self.alpha * (Self::distance(&new_particles[p]) - Self::distance(&particles[p])))
+    }
+
+    fn grad_log_psi<D: Dim>(&self, p: usize, particles: &[Particle], _: &()) -> Result<Vector, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        Ok(particles[p].position.scale(-self.alpha / Self::distance_off_cusp(&particles[p])?))
+    }
+
+    fn laplacian_log_psi<D: Dim>(&self, p: usize, particles: &[Particle], _: &()) -> Result<f64, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        Ok(-self.alpha * (particles[p].dim - 1) as f64 / Self::distance_off_cusp(&particles[p])?)
+    }
+
+    fn log_derivatives<D: Dim>(&self, particles: &[Particle], _: &()) -> Result<Vec<f64>, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        Ok(vec![-particles.iter().map(Self::distance).sum::<f64>()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{check_random, monte_carlo, BruteForceMetropolis, ImportanceMetropolis, Metropolis, System};
+    use nalgebra::{Const, Dynamic};
+
+    #[test]
+    fn test_exact_ground_states() {
+        // Without interactions, the local energy of an eigenstate is the same everywhere
+        let bosons = GaussianProduct { alpha: 1., omega: 0.5, anisotropy: [1., 2., 1.] };
+        let mut sys: System<Dynamic, _> = System::new(5, 2, bosons, false, false, 1., 1).unwrap();
+        let vals = monte_carlo(500, &mut sys, &mut ImportanceMetropolis::new(0.)).unwrap();
+        // Each boson has energy (0.5 + 1) / 2
+        assert!((vals.map["energy"] - 3.75).abs() < 1e-10);
+        assert!(vals.derivatives.energy_gradient(vals.map["energy"])[0].abs() < 1e-10);
+
+        let hydrogen = HydrogenOrbital { alpha: 2., charge: 2. };
+        let mut sys: System<Const<2>, _> = System::new(2, 3, hydrogen, false, false, 1., 2).unwrap();
+        let vals = monte_carlo(500, &mut sys, &mut BruteForceMetropolis::new(0.5)).unwrap();
+        assert!((vals.map["energy"] + 4.).abs() < 1e-10);
+
+        let hydrogen = HydrogenOrbital { alpha: 2., charge: 1. };
+        let mut sys: System<Const<2>, _> = System::new(2, 2, hydrogen, false, false, 1., 3).unwrap();
+        let vals = monte_carlo(500, &mut sys, &mut BruteForceMetropolis::new(0.5)).unwrap();
+        assert!((vals.map["energy"] + 4.).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_analytic_derivatives_agree() {
+        let bosons = GaussianProduct { alpha: 0.9, omega: 0.8, anisotropy: [1., 1.1, 1.3] };
+        let hydrogen = HydrogenOrbital { alpha: 0.9, charge: 1. };
+        for &dim in [1, 2, 3].iter() {
+            assert!(check_random::<Dynamic, _>(&bosons, 4, dim, 5, 1., dim as u64, 1e-4).unwrap().max() < 1e-5);
+            assert!(check_random::<Dynamic, _>(&hydrogen, 3, dim, 5, 1., dim as u64, 1e-4).unwrap().max() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_hydrogen_cusp() {
+        let hydrogen = HydrogenOrbital { alpha: 1., charge: 1. };
+        let particles = vec![Particle::new(3).unwrap(), Particle::from_vector(Vector::D3(0., 0.5, 0.))];
+        assert!(hydrogen.grad_log_psi::<Dynamic>(0, &particles, &()).is_err());
+        assert!(hydrogen.laplacian_log_psi::<Dynamic>(0, &particles, &()).is_err());
+        assert!(hydrogen.grad_log_psi::<Dynamic>(1, &particles, &()).is_ok());
+        assert!((hydrogen.laplacian_log_psi::<Dynamic>(1, &particles, &()).unwrap() + 4.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_log_ratio() {
+        let wf = HydrogenOrbital { alpha: 0.8, charge: 1. };
+        let mut sys: System<Dynamic, _> = System::new(3, 3, wf, true, false, 1., 4).unwrap();
+        for _ in 0..10 {
+            let (new_particles, p) = sys.random_particle_change(0.5);
+            let want = sys.wf.log_psi(&new_particles).unwrap() - sys.wf.log_psi(&sys.particles).unwrap();
+            assert!((sys.log_ratio(p, &new_particles).unwrap() - want).abs() < 1e-12);
+            sys.accept_move(p, new_particles).unwrap();
+        }
+    }
+}
@@ -1,13 +1,11 @@
-use nalgebra::{SMatrix, DimSub};    
+use nalgebra::DMatrix;
 use std::{
     env,
     fs::{create_dir_all, File},
-    io::prelude::*,
     path::{Path, PathBuf},
-    time::Instant,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Spin {
     Up,
     Down,
@@ -22,53 +20,25 @@ pub fn a(i: usize, j: usize, n: usize) -> f64 {
     }
 }
 
-// This allocates a lot more than I would've preferred, but when nalgebra refuses to calculate a
-// simple determinant, then this'll have to do...
-pub fn det<const N: usize>(mat: Option<&SMatrix<f64, N, N>>, vec: Option<&Vec<Vec<f64>>>) -> Option<f64> {
-    let mut vec_mat: Vec<Vec<f64>>;
-    match mat {
-        Some(mat) => {
-            vec_mat = vec![vec![0.; N]; N];
-            for i in 0..N {
-                for j in 0..N {
-                    vec_mat[i][j] = mat[(i, j)];
-                }
-            }
-        },
-        None => {
-            vec_mat = vec.unwrap().clone();
+/// Returns the sign and the logarithm of the absolute value of the determinant of `matrix`, from
+/// its LU decomposition with partial pivoting. Working with the logarithm keeps large matrices from
+/// overflowing or underflowing. A singular matrix gives a sign of 0 and a logarithm of -infinity.
+pub fn log_det(matrix: DMatrix<f64>) -> (f64, f64) {
+    let lu = matrix.lu();
+    let mut sign: f64 = lu.p().determinant();
+    let mut log = 0.;
+    for &pivot in lu.u().diagonal().iter() {
+        if pivot == 0. {
+            return (0., f64::NEG_INFINITY);
         }
+        sign *= pivot.signum();
+        log += pivot.abs().ln();
     }
-
-    // Recursive determinant calculation
-    match vec_mat.len() {
-        1 => Some(vec_mat[0][0]),
-        _ => Some(vec_mat[0]
-                .iter()
-                .enumerate()
-                .map(|(i, val)| -1f64.powi(i as i32) * val * det::<N>(None, Some(&sub(&vec_mat, i))).unwrap())
-                .sum::<f64>(),
-        ),
-    }
+    (sign, log)
 }
 
-fn sub(mat: &Vec<Vec<f64>>, col: usize) -> Vec<Vec<f64>> {
-    let mut sub: Vec<Vec<f64>> = mat.clone();
-
-    // Pop first vec item (first row in matrix)
-    sub.remove(0);
-
-    // Iterate over rows, remove item at col
-    for i in 0..sub.len() {
-        sub[i].remove(col);
-    }
-
-    sub
-}
-
-
 pub fn find_cargo_root() -> Option<PathBuf> {
-    let mut path: PathBuf = env::current_dir().unwrap().into();
+    let mut path: PathBuf = env::current_dir().unwrap();
     let file = Path::new("Cargo.toml");
 
     loop {
@@ -85,15 +55,46 @@ pub fn find_cargo_root() -> Option<PathBuf> {
     }
 }
 
-pub fn create_dir(path: &PathBuf) {
-    if Path::new(path).exists() == false {
+pub fn create_dir(path: &Path) {
+    if !path.exists() {
         create_dir_all(path).expect("Unable to create folder");
     }
 }
 
-pub fn create_file(filepath: &PathBuf) -> File {
+pub fn create_file(filepath: &Path) -> File {
     match File::create(filepath) {
         Ok(f) => f,
         Err(why) => panic!("Unable to create {:?}: {}", filepath, why),
     }
-}
\ No newline at end of file
+}
+
+/// Derives the seed of an independent random number stream from a master seed, so that every
+/// thread or repeated run gets its own stream while the whole computation is given by one seed.
+/// Uses the SplitMix64 mixing function, keeping 63 bits of it since the seed is written to TOML
+/// files, whose integers are signed.
+pub fn stream_seed(master: u64, stream: u64) -> u64 {
+    let mut z = master.wrapping_add((stream + 1).wrapping_mul(0x9E3779B97F4A7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    (z ^ (z >> 31)) >> 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_det() {
+        let matrix = DMatrix::from_row_slice(3, 3, &[0., 2., 1., 1., 1., 0., 3., 0., 2.]);
+        let (sign, log) = log_det(matrix.clone());
+        assert!((sign * log.exp() - matrix.determinant()).abs() < 1e-12);
+        assert_eq!(sign, -1.);
+
+        // Far beyond what an f64 determinant can hold
+        let (sign, log) = log_det(DMatrix::from_diagonal_element(200, 200, 1e-3));
+        assert_eq!(sign, 1.);
+        assert!((log - 200. * 1e-3f64.ln()).abs() < 1e-9);
+
+        assert_eq!(log_det(DMatrix::from_element(2, 2, 1.)), (0., f64::NEG_INFINITY));
+    }
+}
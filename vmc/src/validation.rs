@@ -0,0 +1,170 @@
+// Checks the analytic derivatives of a trial wavefunction against finite differences of
+// `TrialWaveFunction::log_psi`. The finite differences are slow and not very accurate, but they only
+// depend on the wavefunction being right, so any disagreement points at a bug in the analytic
+// expressions.
+
+use crate::{utils::stream_seed, System, TrialWaveFunction};
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim};
+
+use std::fmt;
+
+/// Largest differences between analytic derivatives and their finite-difference counterparts.
+/// Each difference is measured as |analytic - numerical| / (1 + |numerical|), so it is absolute for
+/// small values and relative for large ones.
+#[derive(Clone, Debug, Default)]
+pub struct Discrepancies {
+    /// Largest difference of any component of the gradient of the logarithm of the wavefunction,
+    /// per particle
+    pub gradient: Vec<f64>,
+    /// Difference of the Laplacian of the logarithm of the wavefunction, per particle
+    pub laplace: Vec<f64>,
+    /// Largest difference of any component of the quantum force, per particle
+    pub quantum_force: Vec<f64>,
+    /// Difference of the derivative with regards to each variational parameter, divided by the
+    /// wavefunction, together with the name of the parameter
+    pub parameters: Vec<(&'static str, f64)>,
+}
+
+impl Discrepancies {
+    fn new(n: usize, parameter_names: Vec<&'static str>) -> Self {
+        Discrepancies {
+            gradient: vec![0.; n],
+            laplace: vec![0.; n],
+            quantum_force: vec![0.; n],
+            parameters: parameter_names.into_iter().map(|name| (name, 0.)).collect(),
+        }
+    }
+
+    /// Keeps the largest discrepancy of each kind from either `self` or `other`
+    fn merge(&mut self, other: &Self) {
+        let max = |a: &mut Vec<f64>, b: &[f64]| a.iter_mut().zip(b).for_each(|(a, b)| *a = a.max(*b));
+        max(&mut self.gradient, &other.gradient);
+        max(&mut self.laplace, &other.laplace);
+        max(&mut self.quantum_force, &other.quantum_force);
+        for (a, b) in self.parameters.iter_mut().zip(&other.parameters) {
+            a.1 = a.1.max(b.1);
+        }
+    }
+
+    /// Returns the largest discrepancy of any kind
+    pub fn max(&self) -> f64 {
+        self.gradient.iter()
+            .chain(self.laplace.iter())
+            .chain(self.quantum_force.iter())
+            .chain(self.parameters.iter().map(|(_, x)| x))
+            .fold(0., |max, &x| if x.is_nan() { f64::NAN } else { max.max(x) })
+    }
+}
+
+impl fmt::Display for Discrepancies {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:>8} {:>12} {:>12} {:>14}", "particle", "gradient", "laplace", "quantum_force")?;
+        for i in 0..self.gradient.len() {
+            writeln!(f, "{:>8} {:>12.3e} {:>12.3e} {:>14.3e}", i, self.gradient[i], self.laplace[i], self.quantum_force[i])?;
+        }
+        let parameters: Vec<String> = self.parameters.iter()
+            .map(|(name, error)| format!("{}: {:.3e}", name, error))
+            .collect();
+        write!(f, "{}", parameters.join("\n"))
+    }
+}
+
+fn discrepancy(analytic: f64, numerical: f64) -> f64 {
+    (analytic - numerical).abs() / (1. + numerical.abs())
+}
+
+/// Compares every analytic derivative at the current configuration of `sys` with central
+/// differences of step `h`.
+pub fn check<D: Dim, W: TrialWaveFunction + Clone>(sys: &System<D, W>, h: f64) -> Result<Discrepancies, String>
+where
+    DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+{
+    let n = sys.particles.len();
+    let mut result = Discrepancies::new(n, sys.wf.parameter_names());
+
+    for i in 0..n {
+        let gradient = sys.wf.grad_log_psi(i, &sys.particles, &sys.state)?;
+        let quantum_force = sys.wf.quantum_force(i, &sys.particles, &sys.state)?;
+        let (numerical_gradient, numerical_laplace) = sys.wf.log_psi_derivatives_numerical(i, &sys.particles, h)?;
+
+        for d in 0..sys.dim {
+            let numerical = numerical_gradient.get(d).unwrap();
+            let error = discrepancy(gradient.get(d).unwrap(), numerical);
+            result.gradient[i] = result.gradient[i].max(error);
+            let error = discrepancy(quantum_force.get(d).unwrap(), 2. * numerical);
+            result.quantum_force[i] = result.quantum_force[i].max(error);
+        }
+        let laplace = sys.wf.laplacian_log_psi(i, &sys.particles, &sys.state)?;
+        result.laplace[i] = discrepancy(laplace, numerical_laplace);
+    }
+
+    // The parameter derivatives are taken of the logarithm, which is what the analytic ones are
+    let parameters = sys.wf.parameters();
+    let log_psi = |k: usize, shift: f64| {
+        let mut shifted = parameters.clone();
+        shifted[k] += shift;
+        let mut wf = sys.wf.clone();
+        wf.set_parameters(&shifted)?;
+        wf.log_psi(&sys.particles)
+    };
+    let analytic = sys.wf.log_derivatives(&sys.particles, &sys.state)?;
+    for (k, analytic) in analytic.into_iter().enumerate() {
+        let numerical = (log_psi(k, h)? - log_psi(k, -h)?) / (2. * h);
+        result.parameters[k].1 = discrepancy(analytic, numerical);
+    }
+
+    Ok(result)
+}
+
+/// Runs `check` on `configurations` random configurations of `n` particles in `dim` dimensions,
+/// spread out by `spread`, and returns the largest discrepancies seen for each particle.
+pub fn check_random<D: Dim, W: TrialWaveFunction + Clone>(
+    wf: &W,
+    n: usize,
+    dim: usize,
+    configurations: usize,
+    spread: f64,
+    seed: u64,
+    h: f64,
+) -> Result<Discrepancies, String>
+where
+    DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+{
+    let mut result = Discrepancies::new(n, wf.parameter_names());
+    for i in 0..configurations {
+        let sys: System<D, W> = System::new(n, dim, wf.clone(), true, false, spread, stream_seed(seed, i as u64))?;
+        result.merge(&check(&sys, h)?);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WaveFunction;
+    use nalgebra::Const;
+
+    #[test]
+    fn test_analytic_derivatives_agree() {
+        for &jastrow_on in [false, true].iter() {
+            let wf = WaveFunction { alpha: 0.9, beta: 0.4, omega: 0.8, anisotropy: [1., 1.1, 1.3], jastrow_on };
+            assert!(check_random::<Const<2>, _>(&wf, 2, 2, 5, 1., 1, 1e-4).unwrap().max() < 1e-5);
+            assert!(check_random::<Const<6>, _>(&wf, 6, 2, 5, 1., 2, 1e-4).unwrap().max() < 1e-5);
+            assert!(check_random::<Const<8>, _>(&wf, 8, 3, 5, 1., 3, 1e-4).unwrap().max() < 1e-5);
+            assert!(check_random::<Const<6>, _>(&wf, 6, 1, 5, 1., 4, 1e-4).unwrap().max() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_detects_discrepancy() {
+        let wf = WaveFunction { alpha: 0.9, beta: 0.4, omega: 1., anisotropy: [1.; 3], jastrow_on: true };
+        let mut sys = System::<Const<6>>::new(6, 2, wf, true, false, 1., 5).unwrap();
+        // A stale inverse makes every Slater derivative wrong
+        sys.state.inverse *= 2.;
+        let result = check(&sys, 1e-4).unwrap();
+        assert!(result.gradient.iter().all(|&error| error > 1e-3));
+        // Alpha enters the Slater determinants, beta only the Jastrow factor
+        assert!(result.parameters[0].1 > 1e-3);
+        assert!(result.parameters[1].1 < 1e-5);
+    }
+}
@@ -0,0 +1,173 @@
+use nalgebra::DMatrix;
+
+/// A trial wavefunction with a vector of variational parameters. Everything that tunes the
+/// wavefunction, from the sampled averages to the optimizers, goes through this vector, so a new
+/// parameter only has to be added here and to `TrialWaveFunction::log_derivatives`.
+pub trait Variational {
+    /// Names of the parameters, in the same order as `parameters`
+    fn parameter_names(&self) -> Vec<&'static str>;
+
+    /// Returns the current values of the variational parameters
+    fn parameters(&self) -> Vec<f64>;
+
+    /// Replaces the variational parameters, which must be given in the same order as `parameters`
+    fn set_parameters(&mut self, parameters: &[f64]) -> Result<(), String>;
+}
+
+/// Sums, and after `monte_carlo` averages, of the logarithmic derivatives O_k of the wavefunction
+/// with regards to each variational parameter. Together with the energy these give the gradient
+/// of the energy and the overlap matrix S used by the optimizers. If the derivatives of the local
+/// energy with regards to the parameters, dE_l, are sampled as well, they also give the Hamiltonian
+/// of the linear method. Otherwise the fields holding them are empty.
+#[derive(Clone, Debug)]
+pub struct ParameterDerivatives {
+    /// <O_k>
+    pub mean: Vec<f64>,
+    /// <O_k E>
+    pub times_energy: Vec<f64>,
+    /// <O_k O_l>
+    pub products: DMatrix<f64>,
+    /// <dE_l>
+    pub energy_derivatives: Vec<f64>,
+    /// <O_k O_l E>
+    pub products_times_energy: DMatrix<f64>,
+    /// <O_k dE_l>
+    pub times_energy_derivatives: DMatrix<f64>,
+}
+
+impl Default for ParameterDerivatives {
+    fn default() -> Self {
+        ParameterDerivatives {
+            mean: vec![],
+            times_energy: vec![],
+            products: DMatrix::zeros(0, 0),
+            energy_derivatives: vec![],
+            products_times_energy: DMatrix::zeros(0, 0),
+            times_energy_derivatives: DMatrix::zeros(0, 0),
+        }
+    }
+}
+
+impl ParameterDerivatives {
+    /// The derivatives of a single sample with local energy `energy`
+    pub fn sample(derivatives: Vec<f64>, energy: f64) -> Self {
+        let n = derivatives.len();
+        ParameterDerivatives {
+            times_energy: derivatives.iter().map(|o| o * energy).collect(),
+            products: DMatrix::from_fn(n, n, |k, l| derivatives[k] * derivatives[l]),
+            mean: derivatives,
+            ..Default::default()
+        }
+    }
+
+    /// Adds the derivatives of the local energy `energy` of the sample, `energy_derivatives`
+    pub fn with_energy_derivatives(mut self, energy: f64, energy_derivatives: Vec<f64>) -> Self {
+        let n = self.mean.len();
+        self.products_times_energy = &self.products * energy;
+        self.times_energy_derivatives = DMatrix::from_fn(n, n, |k, l| self.mean[k] * energy_derivatives[l]);
+        self.energy_derivatives = energy_derivatives;
+        self
+    }
+
+    /// Adds the sums of `other`. An empty sum takes the shape of `other`.
+    pub fn add(&mut self, other: &Self) {
+        if self.mean.is_empty() {
+            *self = other.clone();
+            return;
+        }
+        let add = |a: &mut Vec<f64>, b: &[f64]| a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+        add(&mut self.mean, &other.mean);
+        add(&mut self.times_energy, &other.times_energy);
+        self.products += &other.products;
+        add(&mut self.energy_derivatives, &other.energy_derivatives);
+        self.products_times_energy += &other.products_times_energy;
+        self.times_energy_derivatives += &other.times_energy_derivatives;
+    }
+
+    pub fn divide(&mut self, factor: f64) {
+        self.mean.iter_mut().for_each(|a| *a /= factor);
+        self.times_energy.iter_mut().for_each(|a| *a /= factor);
+        self.products /= factor;
+        self.energy_derivatives.iter_mut().for_each(|a| *a /= factor);
+        self.products_times_energy /= factor;
+        self.times_energy_derivatives /= factor;
+    }
+
+    /// Returns the gradient of the energy with regards to each parameter, 2 (<O_k E> - <O_k> <E>),
+    /// given the mean energy
+    pub fn energy_gradient(&self, energy: f64) -> Vec<f64> {
+        self.mean.iter()
+            .zip(&self.times_energy)
+            .map(|(o, oe)| 2. * (oe - o * energy))
+            .collect()
+    }
+
+    /// Returns the covariance of the derivatives, S_kl = <O_k O_l> - <O_k> <O_l>
+    pub fn covariance(&self) -> DMatrix<f64> {
+        let n = self.mean.len();
+        DMatrix::from_fn(n, n, |k, l| self.products[(k, l)] - self.mean[k] * self.mean[l])
+    }
+
+    /// Returns the Hamiltonian and overlap matrices of the linear method, given the mean energy, or
+    /// `None` if the derivatives of the local energy were not sampled. They are taken in the basis
+    /// of the wavefunction itself followed by its derivatives (O_k - <O_k>) psi, which are orthogonal
+    /// to it. The Hamiltonian is not symmetric, since the derivatives of the local energy only enter
+    /// on one side, which makes its estimate obey a zero-variance principle like the energy does.
+    pub fn linear_method(&self, energy: f64) -> Option<(DMatrix<f64>, DMatrix<f64>)> {
+        let n = self.mean.len();
+        if self.energy_derivatives.len() != n {
+            return None;
+        }
+        let (o, oe, d) = (&self.mean, &self.times_energy, &self.energy_derivatives);
+
+        let hamiltonian = DMatrix::from_fn(n + 1, n + 1, |k, l| match (k, l) {
+            (0, 0) => energy,
+            (k, 0) => oe[k - 1] - o[k - 1] * energy,
+            (0, l) => oe[l - 1] - o[l - 1] * energy + d[l - 1],
+            (k, l) => {
+                let (k, l) = (k - 1, l - 1);
+                self.products_times_energy[(k, l)] - o[k] * oe[l] - o[l] * oe[k] + o[k] * o[l] * energy
+                    + self.times_energy_derivatives[(k, l)] - o[k] * d[l]
+            }
+        });
+        let covariance = self.covariance();
+        let overlap = DMatrix::from_fn(n + 1, n + 1, |k, l| match (k, l) {
+            (0, 0) => 1.,
+            (0, _) | (_, 0) => 0.,
+            (k, l) => covariance[(k - 1, l - 1)],
+        });
+        Some((hamiltonian, overlap))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_averages() {
+        let mut sum = ParameterDerivatives::default();
+        sum.add(&ParameterDerivatives::sample(vec![1., 2.], 3.));
+        sum.add(&ParameterDerivatives::sample(vec![3., -2.], 1.));
+        sum.divide(2.);
+
+        assert_eq!(sum.mean, vec![2., 0.]);
+        assert_eq!(sum.times_energy, vec![3., 2.]);
+        assert_eq!(sum.covariance(), DMatrix::from_row_slice(2, 2, &[1., -2., -2., 4.]));
+        // <E> = 2
+        assert_eq!(sum.energy_gradient(2.), vec![-2., 4.]);
+        assert!(sum.linear_method(2.).is_none());
+    }
+
+    #[test]
+    fn test_linear_method() {
+        let mut sum = ParameterDerivatives::default();
+        sum.add(&ParameterDerivatives::sample(vec![1.], 3.).with_energy_derivatives(3., vec![1.]));
+        sum.add(&ParameterDerivatives::sample(vec![3.], 1.).with_energy_derivatives(1., vec![-1.]));
+        sum.divide(2.);
+
+        let (hamiltonian, overlap) = sum.linear_method(2.).unwrap();
+        assert_eq!(hamiltonian, DMatrix::from_row_slice(2, 2, &[2., -1., -1., 1.]));
+        assert_eq!(overlap, DMatrix::identity(2, 2));
+    }
+}
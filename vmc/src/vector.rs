@@ -10,6 +10,16 @@ pub enum Vector {
 }
 
 impl Vector {
+    /// Makes a vector with the dimension of the number of `components`, which must be 1, 2 or 3.
+    pub fn from_slice(components: &[f64]) -> Result<Self, String> {
+        match *components {
+            [x] => Ok(D1(x)),
+            [x, y] => Ok(D2(x, y)),
+            [x, y, z] => Ok(D3(x, y, z)),
+            _ => Err(format!("Vectors have 1, 2 or 3 dimensions, not {}.", components.len())),
+        }
+    }
+
     pub fn scale(&self, factor: f64) -> Self {
         match self {
             D1(x) => D1(factor * x),
@@ -29,21 +39,9 @@ impl Vector {
 
     pub fn get(&self, index: usize) -> Option<f64> {
         match self {
-            D1(x) => {
-                if index != 0 {
-                    return None;
-                } else {
-                    Some(*x)
-                }
-            }
-            D2(x, y) => match [*x, *y].get(index) {
-                Some(val) => Some(*val),
-                None => None,
-            },
-            D3(x, y, z) => match [*x, *y, *z].get(index) {
-                Some(val) => Some(*val),
-                None => None,
-            },
+            D1(x) => [*x].get(index).copied(),
+            D2(x, y) => [*x, *y].get(index).copied(),
+            D3(x, y, z) => [*x, *y, *z].get(index).copied(),
         }
     }
 }
@@ -80,6 +78,6 @@ impl Sub for Vector {
 
 impl AddAssign for Vector {
     fn add_assign(&mut self, other: Self) {
-        *self = self.clone() + other;
+        *self = *self + other;
     }
 }
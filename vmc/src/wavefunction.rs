@@ -1,353 +1,517 @@
-use crate::{Hermite, Particle, Spin, Vector, a, det};
-use nalgebra::{SMatrix, base::dimension::DimMin, Const};
-
-// Hard-coding quantum states of up to 20 particles. This is done for speed, an should be
-// generalized if this code is to be used seriously
-pub const QUANTUM_NUMBERS: [(usize, usize, Spin); 20] = [
-    (0, 0, Spin::Up),
-    (0, 0, Spin::Down),
-    (1, 0, Spin::Up),
-    (1, 0, Spin::Down),
-    (0, 1, Spin::Up),
-    (0, 1, Spin::Down),
-    (2, 0, Spin::Up),
-    (2, 0, Spin::Down),
-    (1, 1, Spin::Up),
-    (1, 1, Spin::Down),
-    (0, 2, Spin::Up),
-    (0, 2, Spin::Down),
-    (3, 0, Spin::Up),
-    (3, 0, Spin::Down),
-    (2, 1, Spin::Up),
-    (2, 1, Spin::Down),
-    (1, 2, Spin::Up),
-    (1, 2, Spin::Down),
-    (0, 3, Spin::Up),
-    (0, 3, Spin::Down),
-];
+use crate::{Hamiltonian, Hermite, Particle, TrialWaveFunction, Variational, Vector, a, log_det, basis::{QuantumNumbers, filled_shells, slater_state, spin_block, spin_blocks}, interaction::Coulomb, potential::HarmonicOscillator, system::Refresh};
+use nalgebra::{allocator::Allocator, Const, DMatrix, DefaultAllocator, Dim, OMatrix, OVector};
 
+use std::ops::Range;
+
+/// Slater matrix, or its inverse, of a system of `D` particles. `D` is `Const<N>` when the number
+/// of particles is known at compile time, and `Dynamic` when it is not.
+pub type SlaterMatrix<D> = OMatrix<f64, D, D>;
+
+/// Largest condition number of the Slater matrix we trust its inverse for. Beyond it, round-off in
+/// the inverse is amplified enough to swamp the Sherman-Morrison updates built on top of it.
+const MAX_CONDITION: f64 = 1e12;
+
+/// The product of the spin up and spin down Slater determinants of harmonic oscillator orbitals,
+/// and optionally a Pade-Jastrow factor. This is the trial wavefunction of the electrons in a
+/// quantum dot.
 #[derive(Clone)]
 pub struct WaveFunction {
     pub alpha: f64,
     pub beta: f64,
     pub omega: f64,
+    /// Trap frequency along each axis relative to `omega`. Anything but 1 makes the orbitals
+    /// elongated or flattened along that axis.
+    pub anisotropy: [f64; 3],
     pub jastrow_on: bool,
 }
 
 impl WaveFunction {
     //-- Trial wavefunction --
-    /// Trial wavefunction for the ground state of the two electron/fermion system.
-    /// Returns an f64 representing the wavefunction value
-    pub fn evaluate<const N: usize>(&self, particles: &Vec<Particle>) -> Result<f64, String> {
-        let c: f64 = 1.0; //normalization constant - dont know value
-
-        match particles.len() {
-            // In the case of two particles, evaluating the wavefunction is straight forward.
-            2 => {
-                let r1: f64 = particles[0].squared_sum();
-                let r2: f64 = particles[1].squared_sum();
-                let jastrow = if self.jastrow_on { self.evaluate_jastrow(particles) } else { 0. };
-                //println!("a: {:.16} || o: {:.16} || r1: {:.16} || r2: {:.16} || jast: {:.16}", self.alpha, self.omega, r1, r2, jastrow);
-                Ok(c * (-0.5 * self.alpha * self.omega * (r1 + r2) + jastrow).exp())
-            },
-            // This is the general evaluation, using Slater determinants
-            _ => {
-                let slater_matrix: SMatrix<f64, N, N> = self.slater_matrix(particles)?;
-                let slater_det = det(Some(&slater_matrix), None).unwrap();
-                let jastrow = if self.jastrow_on { self.evaluate_jastrow(particles) } else { 0. };
-                Ok(slater_det * jastrow.exp())
-            },
+    /// Returns the Laplacian of the logarithm of the Slater determinant with regards to particle
+    /// `p`. Only the determinant of the spin of particle p depends on its position.
+    fn laplacian_log_slater<D: Dim>(&self, p: usize, particles: &[Particle], slater_inverse: &SlaterMatrix<D>) -> Result<f64, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D>,
+    {
+        let mut laplace_slater = 0.;
+        for k in spin_block(p, particles.len()) {
+            let state = slater_state(k, particles.len(), particles[p].dim);
+            laplace_slater += self.laplace_spf(&particles[p], &state)? * slater_inverse[(k, p)];
         }
+        let gradient_slater = self.gradient_slater(p, particles, slater_inverse)?;
+        Ok(laplace_slater - gradient_slater.inner(gradient_slater)?)
     }
 
-    fn evaluate_jastrow(&self, particles: &Vec<Particle>) -> f64 {
+    fn evaluate_jastrow(&self, particles: &[Particle]) -> f64 {
         let mut jastrow = 0.;
         let n = particles.len();
         for (i, particle) in particles.iter().enumerate() {
-            for (j, other) in particles[i + 1..].iter().enumerate() {
-                let distance = particle.distance_to(&other).unwrap();
+            for (j, other) in particles.iter().enumerate().skip(i + 1) {
+                let distance = particle.distance_to(other).unwrap();
                 jastrow += a(i, j, n) * distance / (1. + self.beta * distance)
             }
         }
         jastrow
     }
 
-    pub fn slater_matrix<const N: usize>(&self, particles: &Vec<Particle>) -> Result<SMatrix<f64, N, N>, String> {
+    /// Returns the Slater matrix of the particles in `block`, with one row for each particle and
+    /// one column for each state of their spin.
+    fn slater_block(&self, particles: &[Particle], block: &Range<usize>) -> Result<DMatrix<f64>, String> {
         let n = particles.len();
-        let mut slater_matrix: SMatrix<f64, N, N> = SMatrix::repeat(0.);
-        for i in 0..n {
-            for j in 0..n {
-                let nx = crate::QUANTUM_NUMBERS.get(i)
-                    .ok_or("System can not have more than 20 particles.")?.0;
-                let ny = crate::QUANTUM_NUMBERS.get(i)
-                    .ok_or("System can not have more than 20 particles.")?.1;
-                slater_matrix[(i, j)] = self.spf(&particles[j], nx, ny).unwrap();
+        let mut matrix = DMatrix::zeros(block.len(), block.len());
+        for (row, p) in block.clone().enumerate() {
+            for (column, k) in block.clone().enumerate() {
+                let state = slater_state(k, n, particles[p].dim);
+                matrix[(row, column)] = self.spf(&particles[p], &state)?;
             }
         }
-        Ok(slater_matrix)
+        Ok(matrix)
     }
 
-    /// Evaluates the single particle wave function  
-    pub fn spf(&self, particle: &Particle, nx: usize, ny: usize) -> Result<f64, String> {
-        let sqrt_alpha_omega = (self.alpha * self.omega).sqrt();
-        let result = match particle.position {
-            Vector::D2(x, y) => {
-                Hermite::evaluate(sqrt_alpha_omega * x, nx)?
-                    * Hermite::evaluate(sqrt_alpha_omega * y, ny)?
-            }
-            _ => return Err("spf only supports two dimension right now".to_owned()),
-        };
-
-        Ok(result * (-0.5 * self.alpha * self.omega * particle.position.inner(particle.position)?).exp())
+    /// Returns the Slater matrix, with one row for each particle and one column for each state. A
+    /// particle only enters the determinant of its own spin, so the matrix is block diagonal, with
+    /// the spin up determinant in the upper left block and the spin down one in the lower right.
+    pub fn slater_matrix<D: Dim>(&self, particles: &[Particle]) -> Result<SlaterMatrix<D>, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D>,
+    {
+        let n = D::from_usize(particles.len());
+        let mut slater_matrix = SlaterMatrix::<D>::zeros_generic(n, n);
+        for block in spin_blocks(particles.len()).iter() {
+            let (start, len) = (block.start, block.len());
+            slater_matrix.slice_mut((start, start), (len, len)).copy_from(&self.slater_block(particles, block)?);
+        }
+        Ok(slater_matrix)
     }
 
-    // --- Laplacian ---
-    /// Returns the Laplacian of the wavefunction evaluated numerically at state of 'particles'.
-    pub fn laplace_numerical<const N: usize>(
-        &self,
-        particles: &Vec<Particle>,
-    ) -> Result<f64, String> {
-        let h: f64 = 0.000001; //stepsize
-        let h2 = h.powi(2);
-
-        let mut laplace = 0.;
-        let mut particles = particles.clone();
-
-        let wf = self.evaluate::<N>(&particles)?;
-
-        for i in 0..particles.len() {
-            for dim in 0..particles[i].dim {
-                particles[i].bump_at_dim(dim, h); // Initial position +h
-                let wf_plus = self.evaluate::<N>(&particles)?;
-
-                particles[i].bump_at_dim(dim, -2. * h); // Initial position -h
-                let wf_minus = self.evaluate::<N>(&particles)?;
-
-                particles[i].bump_at_dim(dim, h); // Reset back to initial position
+    /// Returns the product of the spin up and spin down Slater determinants.
+    pub fn slater_determinant(&self, particles: &[Particle]) -> Result<f64, String> {
+        let (sign, log) = self.log_slater_determinant(particles)?;
+        Ok(sign * log.exp())
+    }
 
-                laplace += (wf_plus - 2. * wf + wf_minus) / h2;
-            }
+    /// Returns the sign of the product of the Slater determinants and the logarithm of its absolute
+    /// value. The logarithms of the two determinants are added, so neither has to fit in an f64.
+    pub fn log_slater_determinant(&self, particles: &[Particle]) -> Result<(f64, f64), String> {
+        let mut result = (1., 0.);
+        for block in spin_blocks(particles.len()).iter() {
+            let (sign, log) = log_det(self.slater_block(particles, block)?);
+            result = (result.0 * sign, result.1 + log);
         }
-
-        Ok(laplace)
+        Ok(result)
     }
 
-    /// Returns the Laplacian of the single particle wave function
-    /// Works only in two dimensions right now
-    pub fn laplace_spf(&self, particle: &Particle, nx: usize, ny: usize) -> Result<f64, String> {
-        let omega_alpha = self.omega * self.alpha;
-        let omega_alpha_sqrt = omega_alpha.sqrt();
-
-        match particle.position {
-            Vector::D2(x, y) => {
-                let hnx = Hermite::evaluate(omega_alpha_sqrt * x, nx)?;
-                let hny = Hermite::evaluate(omega_alpha_sqrt * y, ny)?;
-                let d_hnx = Hermite::derivative(omega_alpha_sqrt * x, nx)? * omega_alpha_sqrt;
-                let d_hny = Hermite::derivative(omega_alpha_sqrt * y, ny)? * omega_alpha_sqrt;
-                let dd_hnx = Hermite::double_derivative(omega_alpha_sqrt * x, nx)? * omega_alpha_sqrt;
-                let dd_hny = Hermite::double_derivative(omega_alpha_sqrt * y, ny)? * omega_alpha_sqrt;
-
-                Ok((-0.5 * omega_alpha * particle.squared_sum()).exp()
-                    * (-2.0 * omega_alpha * x * hny * d_hnx - 2.0 * omega_alpha * y * hnx * d_hny
-                        + omega_alpha * hnx * hny * (omega_alpha * particle.squared_sum() - 2.0)
-                        + hny * dd_hnx
-                        + hnx * dd_hny))
+    /// Returns the inverse of the Slater matrix, or `None` if either of the determinants is zero.
+    /// Each spin block is inverted on its own, so the inverse is block diagonal as well.
+    pub fn slater_inverse<D: Dim>(&self, particles: &[Particle]) -> Result<Option<SlaterMatrix<D>>, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D>,
+    {
+        let n = D::from_usize(particles.len());
+        let mut inverse = SlaterMatrix::<D>::zeros_generic(n, n);
+        for block in spin_blocks(particles.len()).iter() {
+            let (start, len) = (block.start, block.len());
+            match self.slater_block(particles, block)?.try_inverse() {
+                Some(inv) => inverse.slice_mut((start, start), (len, len)).copy_from(&inv),
+                None => return Ok(None),
             }
-            _ => return Err("laplace_spf only supports two dimensions right now.".to_owned()),
         }
+        Ok(Some(inverse))
     }
 
-    // --- Gradients ---
-    /// Returns the Laplacian of the wavefunction evaluated numerically at state of 'particles'.
-    pub fn gradient_numerical<const N: usize>(&self, particles: &Vec<Particle>) -> Result<f64, String> {
-        let h: f64 = 0.000001; //stepsize
-        let two_h = 2. * h;
-
-        let mut gradient = 0.;
-        let mut particles = particles.clone();
-
-        for i in 0..particles.len() {
-            for dim in 0..particles[i].dim {
-                particles[i].bump_at_dim(dim, 2. * h); // Initial position +h
-                let wf_plus = self.evaluate::<N>(&particles)?;
-
-                particles[i].bump_at_dim(dim, -2. * h); // Initial position -h
-                let wf_minus = self.evaluate::<N>(&particles)?;
+    /// Returns the trap frequency along axis `d`
+    fn axis_omega(&self, d: usize) -> f64 {
+        self.anisotropy[d] * self.omega
+    }
 
-                gradient += (wf_plus - wf_minus) / two_h;
+    /// Returns the factors of an orbital along each axis, H_n(sqrt(k) x) exp(-k x^2 / 2) where k is
+    /// alpha times the frequency along the axis, together with their first and second derivatives.
+    fn orbital_factors(&self, particle: &Particle, state: &QuantumNumbers) -> Result<Vec<(f64, f64, f64)>, String> {
+        let n = [state.nx, state.ny, state.nz];
+        (0..particle.dim)
+            .map(|d| {
+                let x = particle.position.get(d).unwrap();
+                let k = self.alpha * self.axis_omega(d);
+                let sqrt_k = k.sqrt();
+                let h = Hermite::evaluate(sqrt_k * x, n[d])?;
+                let d_h = Hermite::derivative(sqrt_k * x, n[d])? * sqrt_k;
+                let dd_h = Hermite::double_derivative(sqrt_k * x, n[d])? * k;
+                let gaussian = (-0.5 * k * x * x).exp();
+                Ok((
+                    h * gaussian,
+                    (d_h - k * x * h) * gaussian,
+                    (dd_h - 2. * k * x * d_h + k * (k * x * x - 1.) * h) * gaussian,
+                ))
+            })
+            .collect()
+    }
 
-                particles[i].bump_at_dim(dim, h); // Reset back to initial position
-            }
-        }
+    /// Evaluates the single particle wave function in `state`
+    pub fn spf(&self, particle: &Particle, state: &QuantumNumbers) -> Result<f64, String> {
+        Ok(self.orbital_factors(particle, state)?.iter().map(|f| f.0).product())
+    }
 
-        Ok(gradient)
+    /// Returns the Laplacian of the single particle wave function in `state`
+    pub fn laplace_spf(&self, particle: &Particle, state: &QuantumNumbers) -> Result<f64, String> {
+        let factors = self.orbital_factors(particle, state)?;
+        Ok((0..factors.len())
+            .map(|d| factors.iter().enumerate().map(|(e, f)| if e == d { f.2 } else { f.0 }).product::<f64>())
+            .sum())
     }
+
+    // --- Gradients ---
     /// Returns the gradient for a particle with regards to the non-interacting part of the
     /// wavefunction
-    fn gradient_spf(&self, particle: &Particle, nx: usize, ny: usize) -> Result<Vector, String> {
-        let gradient = particle.position.clone();
-        let omega_alpha = self.omega * self.alpha;
-        let omega_alpha_sqrt = omega_alpha.sqrt();
-        match gradient {
-            Vector::D2(x, y) => {
-                let hnx = Hermite::evaluate(omega_alpha_sqrt * x, nx)?;
-                let hny = Hermite::evaluate(omega_alpha_sqrt * y, ny)?;
-                let d_hnx = Hermite::derivative(omega_alpha_sqrt * x, nx)? * omega_alpha_sqrt;
-                let d_hny = Hermite::derivative(omega_alpha_sqrt * y, ny)? * omega_alpha_sqrt;
-                Ok(Vector::D2(
-                    hny * (d_hnx - hnx * omega_alpha * x),
-                    hnx * (d_hny - hny * omega_alpha * y),
-                ).scale((-0.5 * omega_alpha * particle.squared_sum()).exp()))
-            }
-            _ => return Err("gradient_spf only supports two dimensions right now.".to_owned()),
-        }
+    fn gradient_spf(&self, particle: &Particle, state: &QuantumNumbers) -> Result<Vector, String> {
+        let factors = self.orbital_factors(particle, state)?;
+        let gradient: Vec<f64> = (0..factors.len())
+            .map(|d| factors.iter().enumerate().map(|(e, f)| if e == d { f.1 } else { f.0 }).product())
+            .collect();
+        Vector::from_slice(&gradient)
     }
 
-    pub fn gradient_slater<const N: usize>(&self, p: usize, particles: &Vec<Particle>, slater_inverse: &SMatrix<f64, N, N>) -> Result<Vector, String> {
+    /// Returns the gradient of the Slater determinant with regards to particle `p`, divided by the
+    /// determinant. Only the determinant of the spin of `p` depends on its position.
+    pub fn gradient_slater<D: Dim>(&self, p: usize, particles: &[Particle], slater_inverse: &SlaterMatrix<D>) -> Result<Vector, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D>,
+    {
         let mut gradient = Particle::new(particles[0].dim).unwrap().position;
-        for i in 0..N {
-            let nx = QUANTUM_NUMBERS[i].0;
-            let ny = QUANTUM_NUMBERS[i].1;
-            let d_spf = self.gradient_spf(&particles[p], nx, ny)?;
-            gradient += d_spf.scale(slater_inverse[(i, p)]);
+        for k in spin_block(p, particles.len()) {
+            let state = slater_state(k, particles.len(), particles[p].dim);
+            let d_spf = self.gradient_spf(&particles[p], &state)?;
+            gradient += d_spf.scale(slater_inverse[(k, p)]);
         }
         Ok(gradient)
     }
 
-    pub fn gradient_jastrow(&self, p: usize, particles: &Vec<Particle>) -> Result<Vector, String> {
+    /// Returns the gradient of the Jastrow exponent with regards to particle `p`
+    pub fn gradient_jastrow(&self, p: usize, particles: &[Particle]) -> Result<Vector, String> {
         let mut gradient = Particle::new(particles[0].dim).unwrap().position;
         let n = particles.len();
         for (i, particle) in particles.iter().enumerate() {
             if i == p { continue }
-            let distance = particles[p].distance_to(&particle)?;
+            let distance = particles[p].distance_to(particle)?;
             let factor = a(p, i, n) / (distance * (1. + self.beta * distance).powi(2));
             gradient += (particles[p].position - particle.position).scale(factor);
         }
         Ok(gradient)
     }
 
-    /// Returns the gradient of the wavefunction with regards to alpha
-    pub fn gradient_alpha(&self, particles: &Vec<Particle>) -> Result<f64, String> {
-        match particles.len() {
-            2 => {
-                Ok(-0.5 * self.omega * (particles[0].squared_sum() + particles[1].squared_sum()))
-            },
-            _ => {
-                let mut result = 0.;
-                let factor = 0.5 * (self.omega / self.alpha).sqrt();
-                let omega_alpha_sqrt = (self.omega * self.alpha).sqrt();
-
-                let n = particles.len();
-                for i in 0..n {
-                    for j in 0..n {
-                        let nx = QUANTUM_NUMBERS[j].0;
-                        let ny = QUANTUM_NUMBERS[j].1;
-                        let (x, y) = match particles[i].position {
-                            Vector::D2(x, y) => (x, y),
-                            _ => return Err("gradient_alpha supports only two dimensions for now.".to_owned())
-                        };
-                        let hnx = Hermite::evaluate(omega_alpha_sqrt * x, nx).unwrap();
-                        let hny = Hermite::evaluate(omega_alpha_sqrt * y, ny).unwrap();
-                        let d_alpha_hnx = Hermite::derivative_alpha(nx, x, self.omega, self.alpha)?;
-                        let d_alpha_hny = Hermite::derivative_alpha(ny, y, self.omega, self.alpha)?;
-                        result += factor * x * d_alpha_hnx / hnx + factor * y * d_alpha_hny / hny;
-                    }
-                }
+    /// Returns the derivative of the single particle wave function in `state` with regards to alpha
+    fn derivative_alpha_spf(&self, particle: &Particle, state: &QuantumNumbers) -> Result<f64, String> {
+        let factors = self.orbital_factors(particle, state)?;
+        let mut result = 0.;
+        for (d, &n) in [state.nx, state.ny, state.nz].iter().enumerate().take(particle.dim) {
+            let x = particle.position.get(d).unwrap();
+            let omega = self.axis_omega(d);
+            let k = self.alpha * omega;
+            let d_alpha = (Hermite::derivative_alpha(n, x, omega, self.alpha)?
+                - 0.5 * omega * x * x * Hermite::evaluate(k.sqrt() * x, n)?)
+                * (-0.5 * k * x * x).exp();
+            result += factors.iter().enumerate().map(|(e, f)| if e == d { d_alpha } else { f.0 }).product::<f64>();
+        }
+        Ok(result)
+    }
 
-                Ok(result) //  - n as f64
+    /// Returns the Laplacian of the Jastrow exponent with regards to particle `p`. Each pair term
+    /// u(r) = a r / (1 + beta r) contributes u'' + (dim - 1) u' / r.
+    pub fn laplace_jastrow(&self, p: usize, particles: &[Particle]) -> Result<f64, String> {
+        let n = particles.len();
+        let mut laplace = 0.;
+        for (i, particle) in particles.iter().enumerate() {
+            if i == p { continue }
+            let distance = particles[p].distance_to(particle)?;
+            let denominator = 1. + self.beta * distance;
+            let d_u = a(p, i, n) / denominator.powi(2);
+            let dd_u = -2. * a(p, i, n) * self.beta / denominator.powi(3);
+            laplace += dd_u + (particle.dim - 1) as f64 * d_u / distance;
+        }
+        Ok(laplace)
+    }
+
+    /// Returns the gradient of the wavefunction with regards to alpha, divided by the wavefunction
+    pub fn gradient_alpha<D: Dim>(&self, particles: &[Particle], slater_inverse: &SlaterMatrix<D>) -> Result<f64, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D>,
+    {
+        let mut result = 0.;
+        for (i, particle) in particles.iter().enumerate() {
+            for k in spin_block(i, particles.len()) {
+                let state = slater_state(k, particles.len(), particle.dim);
+                result += self.derivative_alpha_spf(particle, &state)? * slater_inverse[(k, i)];
             }
         }
+        Ok(result)
     }
 
-    /// Returns the gradient of the wavefunction with regards to beta
-    pub fn gradient_beta(&self, particles: &Vec<Particle>) -> Result<f64, String> {
-        match particles.len() {
-            2 => {
+    /// Returns the gradient of the wavefunction with regards to beta, divided by the wavefunction
+    pub fn gradient_beta(&self, particles: &[Particle]) -> Result<f64, String> {
+        let mut result = 0.;
+        let n = particles.len();
+        for (i, particle) in particles.iter().enumerate() {
+            for (j, other) in particles.iter().enumerate().skip(i + 1) {
                 // Can safely unwrap here, since the particles share dimensionality
-                let distance = particles[0].distance_to(&particles[1]).unwrap();
-                Ok(- 1. * distance.powi(2) / (1. + self.beta * distance).powi(2))
-            },
-            _ => {
-                let mut result = 0.;
-                let n = particles.len();
-                for i in 0..n {
-                    for j in 0..n {
-                        if i == j { continue }
-                        // Can safely unwrap here, since the particles share dimensionality
-                        let distance = particles[i].distance_to(&particles[j]).unwrap();
-                        result -= a(i, j, n) * distance.powi(2) / (1. + self.beta * distance).powi(2)
-                    }
-                }
-                Ok(result) //- n as f64
+                let distance = particle.distance_to(other).unwrap();
+                result -= a(i, j, n) * distance.powi(2) / (1. + self.beta * distance).powi(2)
             }
         }
+        Ok(result)
+    }
+
+    /// Returns the logarithm of the ratio between the Jastrow factor with particle `p` moved to its
+    /// position in `new_particles` and the one at `particles`
+    fn log_jastrow_ratio(&self, p: usize, particles: &[Particle], new_particles: &[Particle]) -> f64 {
+        let n = particles.len();
+        let mut result = 0.;
+        for i in 0..n {
+            if i == p { continue }
+            // Can safely unwrap these, as we know the particles share the same dimensionality
+            let old_distance = particles[p].distance_to(&particles[i]).unwrap();
+            let new_distance = new_particles[p].distance_to(&new_particles[i]).unwrap();
+            result += a(i, p, n) * new_distance / (1. + self.beta * new_distance)
+                    - a(i, p, n) * old_distance / (1. + self.beta * old_distance)
+        }
+        result
     }
+}
+
+/// What a `WaveFunction` keeps of the current configuration between moves.
+#[derive(Clone, Debug)]
+pub struct SlaterState<D: Dim>
+where
+    DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+{
+    /// Inverse of the Slater matrix. It is block diagonal, holding the inverses of the spin up and
+    /// spin down determinants.
+    pub inverse: SlaterMatrix<D>,
+    /// Row of the Slater matrix of the particle moved by the last proposed move, and the ratio of
+    /// the Slater determinants after and before it. The Sherman-Morrison update is built on these.
+    row: OVector<f64, D>,
+    ratio: f64,
+}
 
-    // --- Quantum forces ---
-    pub fn quantum_force<const N: usize>(&self, p: usize, particles: &Vec<Particle>, slater_inverse: &SMatrix<f64, N, N>) -> Result<Vector, String> {
-        if particles.len() == 2 {
-            let a = 1.;
-            let r1 = particles[0].position;
-            let r2 = particles[1].position;
-            let r12 = r1 - r2;
-            let r21 = r2 - r1;
-            let distance = particles[0].distance_to(&particles[1])?;
+impl TrialWaveFunction for WaveFunction {
+    type State<D: Dim> = SlaterState<D>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>;
+
+    /// The logarithms of the two determinants and the Jastrow factor are added, so none of them
+    /// has to fit in an f64.
+    fn evaluate_log(&self, particles: &[Particle]) -> Result<(f64, f64), String> {
+        let (sign, log_slater) = self.log_slater_determinant(particles)?;
+        let jastrow = if self.jastrow_on { self.evaluate_jastrow(particles) } else { 0. };
+        Ok((sign, log_slater + jastrow))
+    }
 
-            if distance == 0. { return Ok(Vector::D2(0., 0.)) }
+    /// The harmonic oscillator trap the orbitals are made for, and Coulomb repulsion
+    fn hamiltonian(&self) -> Hamiltonian {
+        Hamiltonian::new(HarmonicOscillator { omega: self.omega, anisotropy: self.anisotropy }, Coulomb)
+    }
 
-            let factor1 = -2. * self.alpha * self.omega;
-            let factor2 = 2. * a / (distance * (1. + self.beta * distance));
+    /// Fails unless the particles fill a whole number of shells, and gives `None` if the Slater
+    /// matrix is singular.
+    fn initial_state<D: Dim>(&self, particles: &[Particle]) -> Result<Option<SlaterState<D>>, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let dim = particles.first().ok_or_else(|| "There are no particles to make a Slater matrix of.".to_owned())?.dim;
+        filled_shells(particles.len(), dim)?;
+        Ok(self.slater_inverse(particles)?.map(|inverse| SlaterState {
+            inverse,
+            row: OVector::<f64, D>::zeros_generic(D::from_usize(particles.len()), Const::<1>),
+            ratio: 0.,
+        }))
+    }
 
-            Ok(r1.scale(factor1) + r12.scale(factor2) + r2.scale(factor1) + r21.scale(factor2))
-        } else {
-            let slater_gradient = self.gradient_slater(p, particles, slater_inverse)?;
-            let jastrow_gradient = self.gradient_jastrow(p, particles)?;
-            Ok((slater_gradient + jastrow_gradient).scale(2.))
+    /// Only the determinant of the spin of `p` changes, so the other one cancels. Its ratio is
+    /// found from the new row of the Slater matrix and the inverse, and both are kept in `state`.
+    fn log_ratio<D: Dim>(
+        &self,
+        p: usize,
+        particles: &[Particle],
+        new_particles: &[Particle],
+        state: &mut SlaterState<D>,
+    ) -> Result<f64, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let n = particles.len();
+        state.ratio = 0.;
+        for k in spin_block(p, n) {
+            let orbital = slater_state(k, n, new_particles[p].dim);
+            state.row[k] = self.spf(&new_particles[p], &orbital)?;
+            state.ratio += state.row[k] * state.inverse[(k, p)];
         }
+        let jastrow = if self.jastrow_on { self.log_jastrow_ratio(p, particles, new_particles) } else { 0. };
+        Ok(state.ratio.abs().ln() + jastrow)
     }
 
-    /// Calculates the quantum force of a particle not interacting with its surrounding particles
-    pub fn quantum_force_non_interacting(&self, particle: &Particle, nx: usize, ny: usize) -> Result<Vector, String> {
-        Ok(self.gradient_spf(particle, nx, ny)?.scale(2.))
+    /// Updates the inverse Slater matrix with the Sherman-Morrison formula. Only the block of the
+    /// spin of `p` is touched, which makes the update O((N/2)^2).
+    fn update_state<D: Dim>(&self, p: usize, _: &[Particle], state: &mut SlaterState<D>) -> Result<(), String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let block = spin_block(p, state.inverse.nrows());
+        let sums: Vec<f64> = block.clone()
+            .map(|j| block.clone().map(|l| state.row[l] * state.inverse[(l, j)]).sum())
+            .collect();
+        // Column p is updated last, since every other column is updated with its old value
+        for (j, s) in block.clone().zip(sums) {
+            if j == p { continue }
+            for k in block.clone() {
+                state.inverse[(k, j)] -= s / state.ratio * state.inverse[(k, p)];
+            }
+        }
+        for k in block {
+            state.inverse[(k, p)] /= state.ratio;
+        }
+        Ok(())
     }
 
-    /// Returns the gradient of the wavefunction with regards to x
-    pub fn gradient_x(&self, particles: &Vec<Particle>, nx: usize, ny: usize) -> f64 {
-        let r1: f64 = particles[0].squared_sum();
-        let r2: f64 = particles[1].squared_sum();
+    /// Replaces the inverse Slater matrix with one computed from scratch, and returns the drift of
+    /// the old one: the largest difference between them, relative to the largest element of the
+    /// new one. Too much drift, or a Slater matrix with a condition number above `MAX_CONDITION`,
+    /// is reported. A singular Slater matrix is always an error.
+    fn refresh_state<D: Dim>(&self, particles: &[Particle], state: &mut SlaterState<D>, refresh: &Refresh) -> Result<f64, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let slater_matrix: SlaterMatrix<D> = self.slater_matrix(particles)?;
+        let inverse = self.slater_inverse::<D>(particles)?
+            .ok_or_else(|| "The Slater matrix is singular, unable to recompute its inverse.".to_owned())?;
+
+        // Condition number in the 1-norm, the largest column sum
+        let condition = slater_matrix.abs().transpose().column_sum().max() * inverse.abs().transpose().column_sum().max();
+        let drift = (&state.inverse - &inverse).abs().max() / inverse.abs().max();
+        state.inverse = inverse;
+
+        if condition > MAX_CONDITION || condition.is_nan() {
+            refresh.report(format!("The Slater matrix is nearly singular, with condition number {:.3e}.", condition))?;
+        }
+        if drift > refresh.tolerance || drift.is_nan() {
+            refresh.report(format!(
+                "The inverse Slater matrix drifted {:.3e} from its recomputed value, above the tolerance {:.3e}.",
+                drift, refresh.tolerance
+            ))?;
+        }
+        Ok(drift)
+    }
 
-        //Hermitian polynomials
-        // TODO: Find alternative solution to avoid repeated code.
-        let omega_alpha_sqrt = (self.omega * self.alpha).sqrt();
-        let hnx = Hermite::evaluate(omega_alpha_sqrt * r1.powf(0.5), nx).unwrap();
-        let hny = Hermite::evaluate(omega_alpha_sqrt * r2.powf(0.5), ny).unwrap();
+    fn grad_log_psi<D: Dim>(&self, p: usize, particles: &[Particle], state: &SlaterState<D>) -> Result<Vector, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let mut gradient = self.gradient_slater(p, particles, &state.inverse)?;
+        if self.jastrow_on {
+            gradient += self.gradient_jastrow(p, particles)?;
+        }
+        Ok(gradient)
+    }
 
-        let d_hnx = Hermite::derivative(omega_alpha_sqrt * r1.powf(0.5), nx).unwrap();
+    fn laplacian_log_psi<D: Dim>(&self, p: usize, particles: &[Particle], state: &SlaterState<D>) -> Result<f64, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let mut result = self.laplacian_log_slater(p, particles, &state.inverse)?;
+        if self.jastrow_on {
+            result += self.laplace_jastrow(p, particles)?;
+        }
+        Ok(result)
+    }
 
-        let gradient: f64 = (-0.5 * self.omega * self.alpha * (r1 + r2)).exp()
-            * hny
-            * (d_hnx - hnx * self.omega * self.alpha * r1.powf(0.5)); //correct ? x*x + y*y = r1 + r2??
+    fn log_derivatives<D: Dim>(&self, particles: &[Particle], state: &SlaterState<D>) -> Result<Vec<f64>, String>
+    where
+        DefaultAllocator: Allocator<f64, D, D> + Allocator<f64, D>,
+    {
+        let mut derivatives = vec![self.gradient_alpha(particles, &state.inverse)?];
+        if self.jastrow_on {
+            derivatives.push(self.gradient_beta(particles)?);
+        }
+        Ok(derivatives)
+    }
+}
 
-        gradient
+impl Variational for WaveFunction {
+    /// Alpha, and beta if the Jastrow factor is on
+    fn parameter_names(&self) -> Vec<&'static str> {
+        if self.jastrow_on { vec!["alpha", "beta"] } else { vec!["alpha"] }
     }
 
-    /// Returns the gradient of the wavefunction with regards to y
-    pub fn gradient_y(&self, particles: &Vec<Particle>, nx: usize, ny: usize) -> f64 {
-        let r1: f64 = particles[0].squared_sum();
-        let r2: f64 = particles[1].squared_sum();
+    fn parameters(&self) -> Vec<f64> {
+        if self.jastrow_on { vec![self.alpha, self.beta] } else { vec![self.alpha] }
+    }
 
-        //Hermitian polynomials
-        // TODO: Find alternative solution to avoid repeated code.
-        let omega_alpha_sqrt = (self.omega * self.alpha).sqrt();
-        let hnx = Hermite::evaluate(omega_alpha_sqrt * r1.powf(0.5), nx).unwrap();
-        let hny = Hermite::evaluate(omega_alpha_sqrt * r2.powf(0.5), ny).unwrap();
+    fn set_parameters(&mut self, parameters: &[f64]) -> Result<(), String> {
+        match (parameters, self.jastrow_on) {
+            (&[alpha, beta], true) => {
+                self.alpha = alpha;
+                self.beta = beta;
+            }
+            (&[alpha], false) => self.alpha = alpha,
+            _ => return Err(format!("Expected the parameters {:?}, got {} values.", self.parameter_names(), parameters.len())),
+        }
+        Ok(())
+    }
+}
 
-        let d_hny = Hermite::derivative(omega_alpha_sqrt * r1.powf(0.5), ny).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Const;
+    use crate::basis::quantum_numbers;
+
+    #[test]
+    fn test_spf_derivatives() {
+        let wf = WaveFunction { alpha: 0.9, beta: 0.4, omega: 0.8, anisotropy: [1., 1.3, 2.8], jastrow_on: false };
+        let h = 1e-4;
+        let positions = [Vector::D1(0.3), Vector::D2(0.3, -0.7), Vector::D3(0.3, -0.7, 0.5)];
+        for position in positions.iter() {
+            let particle = Particle::from_vector(*position);
+            for i in 0..20 {
+                let state = quantum_numbers(i, particle.dim);
+                let spf = wf.spf(&particle, &state).unwrap();
+                let gradient = wf.gradient_spf(&particle, &state).unwrap();
+
+                let mut laplace = 0.;
+                for d in 0..particle.dim {
+                    let mut plus = particle;
+                    plus.bump_at_dim(d, h);
+                    let mut minus = particle;
+                    minus.bump_at_dim(d, -h);
+                    let (spf_plus, spf_minus) = (wf.spf(&plus, &state).unwrap(), wf.spf(&minus, &state).unwrap());
+
+                    let want = (spf_plus - spf_minus) / (2. * h);
+                    assert!((gradient.get(d).unwrap() - want).abs() < 1e-6 * (1. + want.abs()));
+                    laplace += (spf_plus - 2. * spf + spf_minus) / (h * h);
+                }
+                let got = wf.laplace_spf(&particle, &state).unwrap();
+                assert!((got - laplace).abs() < 1e-4 * (1. + laplace.abs()));
+            }
+        }
+    }
 
-        let gradient: f64 = (-0.5 * self.omega * self.alpha * (r1 + r2)).exp()
-            * hnx
-            * (d_hny - hny * self.omega * self.alpha * r2.powf(0.5)); //correct ? x*x + y*y = r1 + r2??
+    #[test]
+    fn test_anisotropic_ground_state() {
+        let wf = WaveFunction { alpha: 1., beta: 0., omega: 0.5, anisotropy: [1., 1., 2.], jastrow_on: false };
+        let particle = Particle::from_vector(Vector::D3(0.2, 0.4, 0.6));
+        let ground_state = quantum_numbers(0, 3);
+        let want = (-0.5 * wf.omega * particle.squared_sum_scaled_z(2.)).exp();
+        assert!((wf.spf(&particle, &ground_state).unwrap() - want).abs() < 1e-15);
+    }
 
-        gradient
+    #[test]
+    fn test_gradient_alpha() {
+        let wf = WaveFunction { alpha: 0.9, beta: 0.4, omega: 1., anisotropy: [1., 1., 1.5], jastrow_on: false };
+        let sys = crate::System::<Const<8>>::new(8, 3, wf.clone(), false, false, 1., 4).unwrap();
+        let h = 1e-6;
+        let log_det = |alpha: f64| {
+            let wf = WaveFunction { alpha, ..wf.clone() };
+            wf.log_slater_determinant(&sys.particles).unwrap().1
+        };
+        let want = (log_det(wf.alpha + h) - log_det(wf.alpha - h)) / (2. * h);
+        let got = wf.gradient_alpha(&sys.particles, &sys.state.inverse).unwrap();
+        assert!((got - want).abs() < 1e-6 * (1. + want.abs()));
     }
 }